@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rgbmon::{ControllerData, PROTOCOL_VERSION};
+
+// the first byte selects the protocol version, the rest is REQUEST_CONTROLLER_DATA response
+fuzz_target!(|data: &[u8]| {
+    if let Some((protocol, packet)) = data.split_first() {
+        let _ = ControllerData::unpack(0, *protocol as u32 % (PROTOCOL_VERSION + 1), packet);
+    }
+});
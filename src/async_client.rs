@@ -0,0 +1,985 @@
+//! Asynchronous OpenRGB SDK client for tokio (requires "async" feature)
+//!
+//! The client is cheap to clone, all clones share the same connection, so controllers can be
+//! updated concurrently from multiple tasks. Server packets are read by a background task, which
+//! dispatches responses to the waiting requests and broadcasts notifications to subscribers
+//!
+//! Connections are opened with [`AsyncConnector`]s: TCP and Unix sockets natively, any
+//! [`Connector`] (in-memory streams, custom transports) with [`BlockingConnector`]
+use crate::capture::{Capture, Direction};
+use crate::selector::{LedSelector, Selector};
+#[cfg(unix)]
+use crate::transport::UnixConnector;
+use crate::transport::{with_default_port, Connector, TcpConnector};
+use crate::{
+    client_name_request, controller_count, controller_data, decode_response, encode_packet,
+    led_requests, negotiate_protocol, no_response, partially_selected, profile_list, record,
+    update_colors, version_request, ControllerData, ControllerLedSetCommand, DeviceType, Error,
+    ModeData, Notification, PacketHeader, RGBColor, Request, Response,
+};
+use log::{debug, error, warn};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+pub type AsyncReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type AsyncWriter = Box<dyn AsyncWrite + Send + Unpin>;
+pub type ConnectFuture<'a> =
+    Pin<Box<dyn Future<Output = io::Result<(AsyncReader, AsyncWriter)>> + Send + 'a>>;
+type SharedCapture = Arc<Mutex<Option<Capture>>>;
+/// Requests, waiting for responses, by device id and packet type
+type Pending = HashMap<(u32, u32), VecDeque<oneshot::Sender<Vec<u8>>>>;
+
+const NOTIFICATION_QUEUE_SIZE: usize = 16;
+
+/// Opens connections to the server, the async counterpart of [`Connector`]. Called by the client
+/// for every (re)connect
+pub trait AsyncConnector: fmt::Display + Send + Sync {
+    fn connect(&self, timeout: Duration) -> ConnectFuture<'_>;
+}
+
+impl AsyncConnector for TcpConnector {
+    fn connect(&self, _timeout: Duration) -> ConnectFuture<'_> {
+        Box::pin(async move {
+            // tokio tries all resolved addresses
+            let (r, w) = tokio::net::TcpStream::connect(with_default_port(self.addr()))
+                .await?
+                .into_split();
+            Ok((Box::new(r) as AsyncReader, Box::new(w) as AsyncWriter))
+        })
+    }
+}
+
+#[cfg(unix)]
+impl AsyncConnector for UnixConnector {
+    fn connect(&self, _timeout: Duration) -> ConnectFuture<'_> {
+        Box::pin(async move {
+            let (r, w) = tokio::net::UnixStream::connect(self.path())
+                .await?
+                .into_split();
+            Ok((Box::new(r) as AsyncReader, Box::new(w) as AsyncWriter))
+        })
+    }
+}
+
+/// Builds a connector for the path: "unix:/path/to/socket" for Unix sockets, TCP address
+/// otherwise
+pub fn connector(path: &str) -> Box<dyn AsyncConnector> {
+    #[cfg(unix)]
+    if let Some(socket) = path.strip_prefix("unix:") {
+        return Box::new(UnixConnector::new(socket));
+    }
+    Box::new(TcpConnector::new(path))
+}
+
+/// Runs a blocking [`Connector`] (e.g. in-memory streams or a custom transport) for the async
+/// client. Every connection is served by two threads: one writes the data, sent by the client,
+/// the other one blocks on reading the transport
+pub struct BlockingConnector {
+    connector: Arc<dyn Connector>,
+}
+
+impl BlockingConnector {
+    pub fn new(connector: Box<dyn Connector>) -> Self {
+        Self {
+            connector: connector.into(),
+        }
+    }
+}
+
+impl fmt::Display for BlockingConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.connector)
+    }
+}
+
+impl AsyncConnector for BlockingConnector {
+    fn connect(&self, timeout: Duration) -> ConnectFuture<'_> {
+        let connector = self.connector.clone();
+        Box::pin(async move {
+            let mut writer = tokio::task::spawn_blocking(move || connector.connect(timeout))
+                .await
+                .map_err(io::Error::other)??;
+            let mut reader = writer.try_clone()?;
+            let (sent_tx, mut sent_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+            let (received_tx, received_rx) = mpsc::unbounded_channel();
+            // stops when the client drops the connection, the shutdown wakes up the reader
+            thread::spawn(move || {
+                while let Some(data) = sent_rx.blocking_recv() {
+                    if writer.write_all(&data).is_err() {
+                        break;
+                    }
+                }
+                let _ = writer.shutdown();
+            });
+            thread::spawn(move || {
+                let mut buf = vec![0u8; 64 * 1024];
+                loop {
+                    match reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(len) => {
+                            if received_tx.send(buf[..len].to_vec()).is_err() {
+                                break;
+                            }
+                        }
+                        // custom connectors may set read timeouts
+                        Err(e)
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::Interrupted
+                                    | io::ErrorKind::WouldBlock
+                                    | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            if received_tx.is_closed() {
+                                break;
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                }
+                let _ = reader.shutdown();
+            });
+            Ok((
+                Box::new(ChannelReader {
+                    rx: received_rx,
+                    buf: Vec::new(),
+                    pos: 0,
+                }) as AsyncReader,
+                Box::new(ChannelWriter { tx: sent_tx }) as AsyncWriter,
+            ))
+        })
+    }
+}
+
+/// Reads data, received by the blocking connector bridge
+struct ChannelReader {
+    rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl AsyncRead for ChannelReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.pos == self.buf.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(data)) => {
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                // the connection is closed
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let len = std::cmp::min(buf.remaining(), self.buf.len() - self.pos);
+        let pos = self.pos;
+        buf.put_slice(&self.buf[pos..pos + len]);
+        self.pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Passes data to the blocking connector bridge
+struct ChannelWriter {
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl AsyncWrite for ChannelWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(match self.tx.send(buf.to_vec()) {
+            Ok(()) => Ok(buf.len()),
+            Err(_) => Err(io::ErrorKind::BrokenPipe.into()),
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct Connection {
+    writer: AsyncWriter,
+    pending: Arc<Mutex<Pending>>,
+    reader: JoinHandle<()>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+struct State {
+    retries: u8,
+    timeout: Duration,
+    controllers: Vec<ControllerData>,
+    server_protocol: Option<u32>,
+    protocol: Option<u32>,
+}
+
+struct Inner {
+    connector: Box<dyn AsyncConnector>,
+    capture: SharedCapture,
+    connection: tokio::sync::Mutex<Option<Connection>>,
+    state: RwLock<State>,
+    notifications: broadcast::Sender<Notification>,
+}
+
+#[derive(Clone)]
+pub struct AsyncOpenRGBClient {
+    inner: Arc<Inner>,
+}
+
+fn timed_out() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "operation timed out",
+    ))
+}
+
+async fn read_packet<R: AsyncRead + Unpin + ?Sized>(
+    stream: &mut R,
+) -> Result<(u32, u32, Vec<u8>), Error> {
+    let mut buf = [0u8; PacketHeader::SIZE];
+    stream.read_exact(&mut buf).await?;
+    let header = PacketHeader::unpack(&buf)?;
+    let mut data = vec![0u8; header.len as usize];
+    stream.read_exact(&mut data).await?;
+    Ok((header.device_id, header.packet_type, data))
+}
+
+/// Reads server packets until the connection is closed
+async fn read_loop(
+    mut reader: AsyncReader,
+    pending: Arc<Mutex<Pending>>,
+    notifications: broadcast::Sender<Notification>,
+    capture: SharedCapture,
+) {
+    loop {
+        let (device_id, packet_type, data) = match read_packet(&mut reader).await {
+            Ok(v) => v,
+            Err(e) => {
+                debug!("ORGB server connection closed: {}", e);
+                break;
+            }
+        };
+        if let Some(ref mut capture) = *capture.lock().unwrap() {
+            record(
+                capture,
+                Direction::Received,
+                &encode_packet(device_id, packet_type, &data),
+            );
+        }
+        if let Some(n) = Notification::from_packet_type(packet_type) {
+            debug!("server notification: {:?}", n);
+            // no subscribers is not an error
+            let _ = notifications.send(n);
+            continue;
+        }
+        let mut waiting = pending.lock().unwrap();
+        let mut data = data;
+        loop {
+            match waiting
+                .get_mut(&(device_id, packet_type))
+                .and_then(VecDeque::pop_front)
+            {
+                // skip requests, which are timed out or cancelled
+                Some(tx) => match tx.send(data) {
+                    Ok(()) => break,
+                    Err(v) => data = v,
+                },
+                None => {
+                    warn!(
+                        "unexpected packet received: device {} packet {}",
+                        device_id, packet_type
+                    );
+                    break;
+                }
+            }
+        }
+    }
+    // wake up all waiting requests
+    pending.lock().unwrap().clear();
+}
+
+impl AsyncOpenRGBClient {
+    /// Creates a client for the server path: host:port, host name, IP address or
+    /// "unix:/path/to/socket". The connection is opened on the first request
+    pub fn new(path: &str) -> Self {
+        Self::with_connector(connector(path))
+    }
+
+    /// Creates a client with a custom connector
+    pub fn with_connector(connector: Box<dyn AsyncConnector>) -> Self {
+        debug!("ORGB server path set: {}", connector);
+        Self {
+            inner: Arc::new(Inner {
+                connector,
+                capture: Arc::default(),
+                connection: tokio::sync::Mutex::new(None),
+                state: RwLock::new(State {
+                    retries: 3,
+                    timeout: Duration::from_secs(2),
+                    controllers: Vec::new(),
+                    server_protocol: None,
+                    protocol: None,
+                }),
+                notifications: broadcast::channel(NOTIFICATION_QUEUE_SIZE).0,
+            }),
+        }
+    }
+
+    pub fn set_retries(&self, retries: u8) {
+        self.inner.state.write().unwrap().retries = retries;
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.inner.state.write().unwrap().timeout = timeout;
+    }
+
+    /// Controllers, loaded from the server
+    pub fn controllers(&self) -> Vec<ControllerData> {
+        self.inner.state.read().unwrap().controllers.clone()
+    }
+
+    pub fn server_protocol(&self) -> Option<u32> {
+        self.inner.state.read().unwrap().server_protocol
+    }
+
+    pub fn protocol(&self) -> Option<u32> {
+        self.inner.state.read().unwrap().protocol
+    }
+
+    /// Writes all packets, sent and received, to the capture file
+    pub fn set_capture(&self, path: &str) -> Result<(), Error> {
+        *self.inner.capture.lock().unwrap() = Some(Capture::create(path)?);
+        debug!("capturing packets to {}", path);
+        Ok(())
+    }
+
+    /// Subscribes to notifications, sent by the server
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.inner.notifications.subscribe()
+    }
+
+    fn timeout(&self) -> Duration {
+        self.inner.state.read().unwrap().timeout
+    }
+
+    async fn connect(&self) -> Result<Connection, Error> {
+        let path = &self.inner.connector;
+        let timeout = self.timeout();
+        let (reader, writer) = match time::timeout(timeout, path.connect(timeout)).await {
+            Ok(Ok(v)) => v,
+            Ok(Err(e)) => {
+                error!("ORGB server {} error: {}", path, e);
+                return Err(e.into());
+            }
+            Err(_) => {
+                error!("ORGB server {} error: connection timeout", path);
+                return Err(timed_out());
+            }
+        };
+        debug!("ORGB server connected: {}", path);
+        let pending = Arc::new(Mutex::new(Pending::new()));
+        let reader = tokio::spawn(read_loop(
+            reader,
+            pending.clone(),
+            self.inner.notifications.clone(),
+            self.inner.capture.clone(),
+        ));
+        Ok(Connection {
+            writer,
+            pending,
+            reader,
+        })
+    }
+
+    /// Closes the server connection, the next request opens a new one
+    pub async fn disconnect(&self) {
+        self.inner.connection.lock().await.take();
+    }
+
+    pub async fn call(
+        &self,
+        device_id: u32,
+        packet_type: u32,
+        data: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let retries = self.inner.state.read().unwrap().retries;
+        self.call_with_retries(device_id, packet_type, data, retries)
+            .await
+    }
+
+    async fn call_with_retries(
+        &self,
+        device_id: u32,
+        packet_type: u32,
+        data: &[u8],
+        retries: u8,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let mut attempt = 0;
+        loop {
+            match self.call_once(device_id, packet_type, data).await {
+                Ok(v) => return Ok(v),
+                Err(e) => {
+                    attempt += 1;
+                    if attempt > retries {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the request and waits for the response. Only transport errors close the connection:
+    /// on timeouts it is kept for other requests, late responses are skipped by the reader
+    async fn call_once(
+        &self,
+        device_id: u32,
+        packet_type: u32,
+        data: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let timeout = self.timeout();
+        let packet = encode_packet(device_id, packet_type, data);
+        let (rx, pending) = {
+            let mut connection = self.inner.connection.lock().await;
+            // the reader stops when the server closes the connection
+            let closed = match *connection {
+                Some(ref c) => c.reader.is_finished(),
+                None => true,
+            };
+            if closed {
+                *connection = Some(self.connect().await?);
+            }
+            let c = connection.as_mut().unwrap();
+            // the request is registered before sending, so the response can not be missed
+            let rx = if Request::has_response(packet_type) {
+                let (tx, rx) = oneshot::channel();
+                c.pending
+                    .lock()
+                    .unwrap()
+                    .entry((device_id, packet_type))
+                    .or_default()
+                    .push_back(tx);
+                Some(rx)
+            } else {
+                None
+            };
+            // recorded before sending, so the response can not be recorded first
+            if let Some(ref mut capture) = *self.inner.capture.lock().unwrap() {
+                record(capture, Direction::Sent, &packet);
+            }
+            match time::timeout(timeout, c.writer.write_all(&packet)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    connection.take();
+                    return Err(e.into());
+                }
+                Err(_) => {
+                    connection.take();
+                    return Err(timed_out());
+                }
+            }
+            (rx, c.pending.clone())
+        };
+        match rx {
+            Some(rx) => match time::timeout(timeout, rx).await {
+                Ok(Ok(v)) => Ok(Some(v)),
+                Ok(Err(_)) => {
+                    // the reader has stopped, the connection is closed unless it is already
+                    // replaced by another request
+                    let mut connection = self.inner.connection.lock().await;
+                    if connection
+                        .as_ref()
+                        .is_some_and(|c| Arc::ptr_eq(&c.pending, &pending))
+                    {
+                        connection.take();
+                    }
+                    Err(Error::Io(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "connection closed",
+                    )))
+                }
+                Err(_) => Err(timed_out()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Sends the request, using the negotiated protocol version
+    pub async fn request(
+        &self,
+        device_id: u32,
+        request: &Request,
+    ) -> Result<Option<Response>, Error> {
+        let retries = self.inner.state.read().unwrap().retries;
+        self.request_with_retries(device_id, request, retries).await
+    }
+
+    async fn request_with_retries(
+        &self,
+        device_id: u32,
+        request: &Request,
+        retries: u8,
+    ) -> Result<Option<Response>, Error> {
+        let protocol = self.protocol().unwrap_or_default();
+        let data = self
+            .call_with_retries(
+                device_id,
+                request.packet_type(),
+                &request.pack(protocol)?,
+                retries,
+            )
+            .await?;
+        self.update_controller(device_id, |c| c.apply_colors(request));
+        decode_response(device_id, request, protocol, data)
+    }
+
+    async fn query(&self, device_id: u32, request: &Request) -> Result<Response, Error> {
+        self.request(device_id, request)
+            .await?
+            .ok_or_else(no_response)
+    }
+
+    pub async fn load(&self) -> Result<(), Error> {
+        {
+            let mut state = self.inner.state.write().unwrap();
+            state.controllers.clear();
+            state.protocol = None;
+        }
+        // the probe is not retried, no reply means protocol 0
+        let result = self
+            .request_with_retries(0, &version_request(), 0)
+            .await
+            .and_then(|r| r.ok_or_else(no_response));
+        if result.is_err() {
+            self.disconnect().await;
+        }
+        let (server_protocol, protocol) = negotiate_protocol(result)?;
+        {
+            let mut state = self.inner.state.write().unwrap();
+            state.server_protocol = Some(server_protocol);
+            state.protocol = Some(protocol);
+        }
+        self.request(0, &client_name_request()).await?;
+        let count = controller_count(self.query(0, &Request::RequestControllerCount).await?)?;
+        let mut controllers = Vec::new();
+        for i in 0..count {
+            controllers.push(controller_data(
+                self.query(i, &Request::RequestControllerData { protocol })
+                    .await?,
+            )?);
+        }
+        self.inner.state.write().unwrap().controllers = controllers;
+        Ok(())
+    }
+
+    pub async fn reload(&self) -> Result<(), Error> {
+        self.disconnect().await;
+        debug!("reloading");
+        self.load().await
+    }
+
+    pub async fn set_color_by_id(&self, controller_id: u32, color: &RGBColor) -> Result<(), Error> {
+        self.set_colors_by_id(controller_id, |c| vec![*color; c.leds.len()])
+            .await
+    }
+
+    /// Sets LED colors of the controller, the frame is produced by the closure and truncated to
+    /// the number of LEDs
+    pub async fn set_colors_by_id<F>(&self, controller_id: u32, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
+        self.set_colors_where(
+            Some(format!("id {}", controller_id)),
+            |c| c.id == controller_id,
+            f,
+        )
+        .await
+    }
+
+    pub async fn set_color_by_name(
+        &self,
+        controller_name: &str,
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        self.set_colors_by_name(controller_name, |c| vec![*color; c.leds.len()])
+            .await
+    }
+
+    /// Sets LED colors of all controllers with the given name, the frame for each controller is
+    /// produced by the closure
+    pub async fn set_colors_by_name<F>(&self, controller_name: &str, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
+        self.set_colors_where(
+            Some(format!("name {:?}", controller_name)),
+            |c| c.name == controller_name,
+            f,
+        )
+        .await
+    }
+
+    pub async fn set_color_by_device_types(
+        &self,
+        device_types: &[DeviceType],
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        self.set_colors_by_device_types(device_types, |c| vec![*color; c.leds.len()])
+            .await
+    }
+
+    /// Sets LED colors of all controllers of the given types, the frame for each controller is
+    /// produced by the closure
+    pub async fn set_colors_by_device_types<F>(
+        &self,
+        device_types: &[DeviceType],
+        f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
+        self.set_colors_where(
+            Some(format!(
+                "device types {}",
+                device_types
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(",")
+            )),
+            |c| device_types.contains(&c.device_type),
+            f,
+        )
+        .await
+    }
+
+    pub async fn set_color_by_device_type(
+        &self,
+        device_type: DeviceType,
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        self.set_colors_by_device_type(device_type, |c| vec![*color; c.leds.len()])
+            .await
+    }
+
+    /// Sets LED colors of all controllers of the given type, the frame for each controller is
+    /// produced by the closure
+    pub async fn set_colors_by_device_type<F>(
+        &self,
+        device_type: DeviceType,
+        f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
+        self.set_colors_where(
+            Some(format!("device type {}", device_type)),
+            |c| c.device_type == device_type,
+            f,
+        )
+        .await
+    }
+
+    pub async fn set_color_by_selector(
+        &self,
+        selector: &Selector,
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        self.set_colors_by_selector(selector, |c| vec![*color; c.leds.len()])
+            .await
+    }
+
+    /// Sets LED colors of all controllers, matching the selector, the frame for each controller
+    /// is produced by the closure
+    pub async fn set_colors_by_selector<F>(&self, selector: &Selector, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
+        self.set_colors_where(Some(selector.to_string()), |c| selector.matches(c), f)
+            .await
+    }
+
+    pub async fn set_color(&self, color: &RGBColor) -> Result<(), Error> {
+        self.set_colors(|c| vec![*color; c.leds.len()]).await
+    }
+
+    /// Sets LED colors of all controllers, the frame for each controller is produced by the
+    /// closure
+    pub async fn set_colors<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
+        self.set_colors_where(None, |_| true, f).await
+    }
+
+    /// Sets LED colors of the controllers, matching the predicate. If the selector is specified,
+    /// ControllerNotFound is returned when no controllers match
+    async fn set_colors_where<P, F>(
+        &self,
+        selector: Option<String>,
+        predicate: P,
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        P: Fn(&ControllerData) -> bool,
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
+        let to_set: Vec<ControllerLedSetCommand> = self
+            .inner
+            .state
+            .read()
+            .unwrap()
+            .controllers
+            .iter()
+            .filter(|c| predicate(c))
+            .map(|c| ControllerLedSetCommand::new(c, f(c)))
+            .collect();
+        if let Some(selector) = selector {
+            if to_set.is_empty() {
+                return Err(Error::ControllerNotFound(selector));
+            }
+        }
+        for c in to_set {
+            self.request(c.controller_id, &Request::UpdateLeds(c.colors))
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn get_controller(&self, controller_id: u32) -> Result<ControllerData, Error> {
+        self.inner
+            .state
+            .read()
+            .unwrap()
+            .controllers
+            .iter()
+            .find(|c| c.id == controller_id)
+            .cloned()
+            .ok_or_else(|| Error::ControllerNotFound(format!("id {}", controller_id)))
+    }
+
+    /// Updates the controller data, loaded from the server
+    fn update_controller<F>(&self, controller_id: u32, f: F)
+    where
+        F: FnOnce(&mut ControllerData),
+    {
+        if let Some(c) = self
+            .inner
+            .state
+            .write()
+            .unwrap()
+            .controllers
+            .iter_mut()
+            .find(|c| c.id == controller_id)
+        {
+            f(c);
+        }
+    }
+
+    /// Sets colors of a single zone. If less colors than zone LEDs are given, the rest of LEDs
+    /// are left untouched
+    pub async fn set_zone_colors(
+        &self,
+        controller_id: u32,
+        zone_id: u32,
+        colors: &[RGBColor],
+    ) -> Result<(), Error> {
+        let controller = self.get_controller(controller_id)?;
+        let zone = controller
+            .zones
+            .get(zone_id as usize)
+            .ok_or_else(|| Error::NotFound(format!("zone {}", zone_id)))?;
+        let num_colors = std::cmp::min(colors.len(), zone.leds_count as usize);
+        self.request(
+            controller_id,
+            &Request::UpdateZoneLeds {
+                zone_id,
+                colors: colors[..num_colors].to_vec(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_led_color(
+        &self,
+        controller_id: u32,
+        led_id: u32,
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        let controller = self.get_controller(controller_id)?;
+        if led_id as usize >= controller.leds.len() {
+            return Err(Error::NotFound(format!("LED {}", led_id)));
+        }
+        self.request(
+            controller_id,
+            &Request::UpdateSingleLed {
+                led_id,
+                color: *color,
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Sets color of the LEDs, matching any of the selectors, the rest of LEDs are left
+    /// untouched. The current colors of controllers with partially selected LEDs are read from
+    /// the server first, to send a single frame per controller
+    pub async fn set_leds_color(
+        &self,
+        selectors: &[LedSelector],
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        let protocol = self.protocol().unwrap_or_default();
+        let partial = partially_selected(selectors, &self.inner.state.read().unwrap().controllers);
+        for controller_id in partial {
+            let response = self
+                .query(controller_id, &Request::RequestControllerData { protocol })
+                .await?;
+            let mut result = Ok(());
+            self.update_controller(controller_id, |c| result = update_colors(c, response));
+            result?;
+        }
+        let requests = led_requests(
+            selectors,
+            &self.inner.state.read().unwrap().controllers,
+            color,
+        )?;
+        for (controller_id, request) in requests {
+            self.request(controller_id, &request).await?;
+        }
+        Ok(())
+    }
+
+    /// Switches the controller to the mode, keeping the mode parameters loaded from the server
+    pub async fn set_mode(&self, controller_id: u32, mode_id: u32) -> Result<(), Error> {
+        let mode = self
+            .get_controller(controller_id)?
+            .modes
+            .get(mode_id as usize)
+            .ok_or_else(|| Error::NotFound(format!("mode {}", mode_id)))?
+            .clone();
+        self.update_mode(controller_id, mode_id, &mode).await
+    }
+
+    /// Switches the controller to the mode with the given parameters (speed, colors etc.)
+    pub async fn update_mode(
+        &self,
+        controller_id: u32,
+        mode_id: u32,
+        mode: &ModeData,
+    ) -> Result<(), Error> {
+        if mode_id as usize >= self.get_controller(controller_id)?.modes.len() {
+            return Err(Error::NotFound(format!("mode {}", mode_id)));
+        }
+        self.request(
+            controller_id,
+            &Request::UpdateMode {
+                mode_id,
+                mode: mode.clone(),
+            },
+        )
+        .await?;
+        self.update_controller(controller_id, |c| {
+            c.modes[mode_id as usize] = mode.clone();
+            c.active_mode = mode_id as i32;
+        });
+        Ok(())
+    }
+
+    /// Saves the mode to the device memory (requires protocol 3+)
+    pub async fn save_mode(&self, controller_id: u32, mode_id: u32) -> Result<(), Error> {
+        self.check_protocol(3)?;
+        let mode = self
+            .get_controller(controller_id)?
+            .modes
+            .get(mode_id as usize)
+            .ok_or_else(|| Error::NotFound(format!("mode {}", mode_id)))?
+            .clone();
+        self.request(controller_id, &Request::SaveMode { mode_id, mode })
+            .await?;
+        Ok(())
+    }
+
+    /// Switches the controller to the mode, which accepts colors from the SDK (Direct, Custom or
+    /// Static)
+    pub async fn set_custom_mode(&self, controller_id: u32) -> Result<(), Error> {
+        self.get_controller(controller_id)?;
+        self.request(controller_id, &Request::SetCustomMode).await?;
+        self.update_controller(controller_id, |c| {
+            if let Some(mode_id) = c.custom_mode() {
+                c.active_mode = mode_id as i32;
+            }
+        });
+        Ok(())
+    }
+
+    fn check_protocol(&self, required: u32) -> Result<(), Error> {
+        let negotiated = self.protocol().unwrap_or_default();
+        if negotiated < required {
+            Err(Error::UnsupportedVersion {
+                required,
+                negotiated,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns names of the profiles, stored on the server
+    pub async fn profiles(&self) -> Result<Vec<String>, Error> {
+        self.check_protocol(2)?;
+        profile_list(self.query(0, &Request::RequestProfileList).await?)
+    }
+
+    /// Saves the current state of all devices to the server profile
+    pub async fn save_profile(&self, name: &str) -> Result<(), Error> {
+        self.profile_call(Request::SaveProfile(name.to_owned()))
+            .await
+    }
+
+    /// Loads the server profile. The controllers should be reloaded to get the actual modes and
+    /// colors
+    pub async fn load_profile(&self, name: &str) -> Result<(), Error> {
+        self.profile_call(Request::LoadProfile(name.to_owned()))
+            .await
+    }
+
+    pub async fn delete_profile(&self, name: &str) -> Result<(), Error> {
+        self.profile_call(Request::DeleteProfile(name.to_owned()))
+            .await
+    }
+
+    async fn profile_call(&self, request: Request) -> Result<(), Error> {
+        self.check_protocol(2)?;
+        self.request(0, &request).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+//! Load bands: distinct colors for load ranges instead of a continuous gradient
+//!
+//! The text form is FROM-TO:FILL, the range is inclusive, the fill is a color, gradient stops
+//! (positions are relative to the band) or an effect, e.g.
+//!
+//! ```text
+//! 0-20:blue
+//! 20-60:0:blue,100:orange,oklab
+//! 85-100:flash(red,0.5)
+//! ```
+use crate::gradient::Gradient;
+use crate::{Error, RGBColor};
+use std::f32::consts::PI;
+use std::fmt;
+use std::time::Duration;
+
+/// The default effect period
+pub const EFFECT_PERIOD: Duration = Duration::from_secs(1);
+/// The longest effect period
+pub const MAX_EFFECT_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Animated colors
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Effect {
+    /// The color is on for the first half of the period and off for the second one
+    Flash { color: RGBColor, period: Duration },
+    /// The color fades out and in during the period
+    Breathe { color: RGBColor, period: Duration },
+}
+
+impl Effect {
+    /// The color at the time (since the effect start)
+    pub fn color_at(&self, time: Duration) -> RGBColor {
+        match self {
+            Effect::Flash { color, period } => {
+                if phase(time, *period) < 0.5 {
+                    *color
+                } else {
+                    RGBColor::black()
+                }
+            }
+            Effect::Breathe { color, period } => {
+                color.with_brightness((1. + (2. * PI * phase(time, *period)).cos()) / 2.)
+            }
+        }
+    }
+}
+
+/// The position in the period, 0..1
+fn phase(time: Duration, period: Duration) -> f32 {
+    if period.is_zero() {
+        return 0.;
+    }
+    (time.as_secs_f64() % period.as_secs_f64() / period.as_secs_f64()) as f32
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fill {
+    Solid(RGBColor),
+    /// The gradient stop positions are relative to the band: 0% - the band start, 100% - the end
+    Gradient(Gradient),
+    Effect(Effect),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Band {
+    from: u8,
+    to: u8,
+    fill: Fill,
+}
+
+impl Band {
+    /// Creates a band for the load range from-to (percents, inclusive)
+    pub fn new(from: u8, to: u8, fill: Fill) -> Result<Self, Error> {
+        if from > to || to > 100 {
+            return Err(Error::InvalidValue(format!(
+                "band: invalid load range {}-{}",
+                from, to
+            )));
+        }
+        Ok(Self { from, to, fill })
+    }
+
+    pub fn from(&self) -> u8 {
+        self.from
+    }
+
+    pub fn to(&self) -> u8 {
+        self.to
+    }
+
+    pub fn fill(&self) -> &Fill {
+        &self.fill
+    }
+
+    pub fn contains(&self, value: u8) -> bool {
+        (self.from..=self.to).contains(&value)
+    }
+
+    /// The color for the value at the time (for effects)
+    pub fn color(&self, value: u8, time: Duration) -> RGBColor {
+        match self.fill {
+            Fill::Solid(color) => color,
+            Fill::Gradient(ref gradient) => {
+                let position = if self.to > self.from {
+                    value.saturating_sub(self.from) as f32 * 100. / (self.to - self.from) as f32
+                } else {
+                    0.
+                };
+                gradient.color_at(position)
+            }
+            Fill::Effect(ref effect) => effect.color_at(time),
+        }
+    }
+}
+
+impl std::str::FromStr for Effect {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidValue(format!("effect {:?}", s));
+        let (name, args) = s.trim().split_once('(').ok_or_else(invalid)?;
+        let args = args.strip_suffix(')').ok_or_else(invalid)?;
+        // the color may contain commas itself, e.g. rgb(r, g, b)
+        let (color, period) = match args.rsplit_once(',') {
+            Some((color, period)) => match period.trim().parse::<f32>() {
+                Ok(secs) => match Duration::try_from_secs_f32(secs) {
+                    Ok(period) if !period.is_zero() && period <= MAX_EFFECT_PERIOD => {
+                        (color, period)
+                    }
+                    _ => {
+                        return Err(Error::InvalidValue(format!(
+                            "effect {:?}: the period must be greater than 0 and at most {} seconds",
+                            s,
+                            MAX_EFFECT_PERIOD.as_secs()
+                        )))
+                    }
+                },
+                Err(_) => (args, EFFECT_PERIOD),
+            },
+            None => (args, EFFECT_PERIOD),
+        };
+        let color = color.parse()?;
+        match name.trim().to_lowercase().as_str() {
+            "flash" => Ok(Effect::Flash { color, period }),
+            "breathe" => Ok(Effect::Breathe { color, period }),
+            _ => Err(Error::InvalidValue(format!("unknown effect: {}", name))),
+        }
+    }
+}
+
+impl std::str::FromStr for Fill {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let lower = s.to_lowercase();
+        if lower.starts_with("flash(") || lower.starts_with("breathe(") {
+            s.parse().map(Fill::Effect)
+        } else if s.contains(':') {
+            s.parse().map(Fill::Gradient)
+        } else {
+            s.parse().map(Fill::Solid)
+        }
+    }
+}
+
+impl std::str::FromStr for Band {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, fill) = s
+            .split_once(':')
+            .ok_or_else(|| Error::InvalidValue(format!("band {:?}: no fill specified", s)))?;
+        let (from, to) = range
+            .split_once('-')
+            .ok_or_else(|| Error::InvalidValue(format!("band {:?}: no load range", s)))?;
+        let load = |v: &str| {
+            v.trim()
+                .parse()
+                .map_err(|e| Error::InvalidValue(format!("band load {:?}: {}", v, e)))
+        };
+        Self::new(load(from)?, load(to)?, fill.parse()?)
+    }
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Effect::Flash { color, period } => {
+                write!(f, "flash({},{})", color, period.as_secs_f32())
+            }
+            Effect::Breathe { color, period } => {
+                write!(f, "breathe({},{})", color, period.as_secs_f32())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Fill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fill::Solid(color) => write!(f, "{}", color),
+            Fill::Gradient(gradient) => write!(f, "{}", gradient),
+            Fill::Effect(effect) => write!(f, "{}", effect),
+        }
+    }
+}
+
+impl fmt::Display for Band {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}:{}", self.from, self.to, self.fill)
+    }
+}
@@ -0,0 +1,196 @@
+//! Recording and replaying of OpenRGB SDK sessions
+//!
+//! A capture file is a sequence of raw packets, each prefixed with a single direction byte:
+//! b'>' for packets sent by the client and b'<' for packets received from the server
+use crate::{encode_packet, read_packet, Error, PacketHeader, Reader};
+use log::{debug, warn};
+use std::fs::File;
+use std::io::{self, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const DIRECTION_SENT: u8 = b'>';
+const DIRECTION_RECEIVED: u8 = b'<';
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    /// Sent by the client
+    Sent,
+    /// Received from the server
+    Received,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRecord {
+    pub direction: Direction,
+    pub device_id: u32,
+    pub packet_type: u32,
+    pub data: Vec<u8>,
+}
+
+impl CaptureRecord {
+    /// The raw packet: the header and the data
+    pub fn encode(&self) -> Vec<u8> {
+        encode_packet(self.device_id, self.packet_type, &self.data)
+    }
+}
+
+/// Capture file writer. Every packet is flushed immediately, so the capture is complete even if
+/// the process crashes
+pub struct Capture {
+    file: File,
+}
+
+impl Capture {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self {
+            file: File::create(path)?,
+        })
+    }
+
+    pub fn write(&mut self, direction: Direction, packet: &[u8]) -> Result<(), Error> {
+        let mut buf = Vec::with_capacity(packet.len() + 1);
+        buf.push(match direction {
+            Direction::Sent => DIRECTION_SENT,
+            Direction::Received => DIRECTION_RECEIVED,
+        });
+        buf.extend_from_slice(packet);
+        self.file.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+/// Parses a capture
+pub fn unpack_capture(data: &[u8]) -> Result<Vec<CaptureRecord>, Error> {
+    let mut r = Reader::new(data);
+    let mut records = Vec::new();
+    while r.pos < data.len() {
+        let direction = match r.take(1, "direction")?[0] {
+            DIRECTION_SENT => Direction::Sent,
+            DIRECTION_RECEIVED => Direction::Received,
+            v => {
+                r.pos -= 1;
+                return Err(r.malformed("direction", &format!("invalid direction: {}", v)));
+            }
+        };
+        let header = PacketHeader::unpack(r.take(PacketHeader::SIZE, "packet header")?)?;
+        let data = r.take(header.len as usize, "packet data")?;
+        records.push(CaptureRecord {
+            direction,
+            device_id: header.device_id,
+            packet_type: header.packet_type,
+            data: data.to_vec(),
+        });
+    }
+    Ok(records)
+}
+
+/// Reads and parses a capture file
+pub fn read_capture<P: AsRef<Path>>(path: P) -> Result<Vec<CaptureRecord>, Error> {
+    unpack_capture(&std::fs::read(path)?)
+}
+
+/// Serves a captured session back to clients. Every packet received is answered with the server
+/// packets, which followed the corresponding client packet in the capture. The position in the
+/// session is shared between connections, so client reconnects are replayed as well
+pub struct ReplayServer {
+    addr: String,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl ReplayServer {
+    /// Starts the server on a random local port
+    pub fn start(records: Vec<CaptureRecord>) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?.to_string();
+        let records = Arc::new(records);
+        let pos = Arc::new(Mutex::new(0));
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let server = Self {
+            addr,
+            clients: clients.clone(),
+            stopped: stopped.clone(),
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    if let Ok(s) = stream.try_clone() {
+                        clients.lock().unwrap().push(s);
+                    }
+                    let records = records.clone();
+                    let pos = pos.clone();
+                    thread::spawn(move || {
+                        let _ = replay(stream, &records, &pos);
+                    });
+                }
+            }
+        });
+        debug!("replay server started: {}", server.addr);
+        Ok(server)
+    }
+
+    /// Starts the server for a capture file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::start(read_capture(path)?)?)
+    }
+
+    /// Server address (host:port)
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+}
+
+impl Drop for ReplayServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wake up the listener
+        let _ = TcpStream::connect(&self.addr);
+        for client in self.clients.lock().unwrap().iter() {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn replay(
+    mut stream: TcpStream,
+    records: &[CaptureRecord],
+    pos: &Mutex<usize>,
+) -> Result<(), Error> {
+    loop {
+        let (device_id, packet_type, _) = read_packet(&mut stream, None)?;
+        let mut pos = pos.lock().unwrap();
+        let sent = match records[*pos..]
+            .iter()
+            .position(|r| r.direction == Direction::Sent)
+        {
+            Some(v) => *pos + v,
+            None => {
+                warn!("replay: capture exhausted, closing the connection");
+                return Ok(());
+            }
+        };
+        let expected = &records[sent];
+        if expected.device_id != device_id || expected.packet_type != packet_type {
+            warn!(
+                "replay: device {} packet {} received, captured device {} packet {}",
+                device_id, packet_type, expected.device_id, expected.packet_type
+            );
+        }
+        *pos = sent + 1;
+        while let Some(r) = records.get(*pos) {
+            if r.direction == Direction::Sent {
+                break;
+            }
+            stream.write_all(&r.encode())?;
+            *pos += 1;
+        }
+    }
+}
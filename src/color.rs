@@ -0,0 +1,1019 @@
+//! Color parsing and color space conversions
+//!
+//! Accepted text formats: RRGGBB, #RRGGBB, #RGB, CSS/X11 color names (CSS values are used where X11
+//! differs, spaces, "-" and "_" in names are ignored), rgb(r, g, b) with components 0-255 or
+//! percents and hsl(h, s%, l%)
+use crate::{Error, RGBColor};
+
+/// CSS named colors
+const COLOR_NAMES: [(&str, u32); 148] = [
+    ("aliceblue", 0xF0F8FF),
+    ("antiquewhite", 0xFAEBD7),
+    ("aqua", 0x00FFFF),
+    ("aquamarine", 0x7FFFD4),
+    ("azure", 0xF0FFFF),
+    ("beige", 0xF5F5DC),
+    ("bisque", 0xFFE4C4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xFFEBCD),
+    ("blue", 0x0000FF),
+    ("blueviolet", 0x8A2BE2),
+    ("brown", 0xA52A2A),
+    ("burlywood", 0xDEB887),
+    ("cadetblue", 0x5F9EA0),
+    ("chartreuse", 0x7FFF00),
+    ("chocolate", 0xD2691E),
+    ("coral", 0xFF7F50),
+    ("cornflowerblue", 0x6495ED),
+    ("cornsilk", 0xFFF8DC),
+    ("crimson", 0xDC143C),
+    ("cyan", 0x00FFFF),
+    ("darkblue", 0x00008B),
+    ("darkcyan", 0x008B8B),
+    ("darkgoldenrod", 0xB8860B),
+    ("darkgray", 0xA9A9A9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xA9A9A9),
+    ("darkkhaki", 0xBDB76B),
+    ("darkmagenta", 0x8B008B),
+    ("darkolivegreen", 0x556B2F),
+    ("darkorange", 0xFF8C00),
+    ("darkorchid", 0x9932CC),
+    ("darkred", 0x8B0000),
+    ("darksalmon", 0xE9967A),
+    ("darkseagreen", 0x8FBC8F),
+    ("darkslateblue", 0x483D8B),
+    ("darkslategray", 0x2F4F4F),
+    ("darkslategrey", 0x2F4F4F),
+    ("darkturquoise", 0x00CED1),
+    ("darkviolet", 0x9400D3),
+    ("deeppink", 0xFF1493),
+    ("deepskyblue", 0x00BFFF),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1E90FF),
+    ("firebrick", 0xB22222),
+    ("floralwhite", 0xFFFAF0),
+    ("forestgreen", 0x228B22),
+    ("fuchsia", 0xFF00FF),
+    ("gainsboro", 0xDCDCDC),
+    ("ghostwhite", 0xF8F8FF),
+    ("gold", 0xFFD700),
+    ("goldenrod", 0xDAA520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xADFF2F),
+    ("grey", 0x808080),
+    ("honeydew", 0xF0FFF0),
+    ("hotpink", 0xFF69B4),
+    ("indianred", 0xCD5C5C),
+    ("indigo", 0x4B0082),
+    ("ivory", 0xFFFFF0),
+    ("khaki", 0xF0E68C),
+    ("lavender", 0xE6E6FA),
+    ("lavenderblush", 0xFFF0F5),
+    ("lawngreen", 0x7CFC00),
+    ("lemonchiffon", 0xFFFACD),
+    ("lightblue", 0xADD8E6),
+    ("lightcoral", 0xF08080),
+    ("lightcyan", 0xE0FFFF),
+    ("lightgoldenrodyellow", 0xFAFAD2),
+    ("lightgray", 0xD3D3D3),
+    ("lightgreen", 0x90EE90),
+    ("lightgrey", 0xD3D3D3),
+    ("lightpink", 0xFFB6C1),
+    ("lightsalmon", 0xFFA07A),
+    ("lightseagreen", 0x20B2AA),
+    ("lightskyblue", 0x87CEFA),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xB0C4DE),
+    ("lightyellow", 0xFFFFE0),
+    ("lime", 0x00FF00),
+    ("limegreen", 0x32CD32),
+    ("linen", 0xFAF0E6),
+    ("magenta", 0xFF00FF),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66CDAA),
+    ("mediumblue", 0x0000CD),
+    ("mediumorchid", 0xBA55D3),
+    ("mediumpurple", 0x9370DB),
+    ("mediumseagreen", 0x3CB371),
+    ("mediumslateblue", 0x7B68EE),
+    ("mediumspringgreen", 0x00FA9A),
+    ("mediumturquoise", 0x48D1CC),
+    ("mediumvioletred", 0xC71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xF5FFFA),
+    ("mistyrose", 0xFFE4E1),
+    ("moccasin", 0xFFE4B5),
+    ("navajowhite", 0xFFDEAD),
+    ("navy", 0x000080),
+    ("oldlace", 0xFDF5E6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6B8E23),
+    ("orange", 0xFFA500),
+    ("orangered", 0xFF4500),
+    ("orchid", 0xDA70D6),
+    ("palegoldenrod", 0xEEE8AA),
+    ("palegreen", 0x98FB98),
+    ("paleturquoise", 0xAFEEEE),
+    ("palevioletred", 0xDB7093),
+    ("papayawhip", 0xFFEFD5),
+    ("peachpuff", 0xFFDAB9),
+    ("peru", 0xCD853F),
+    ("pink", 0xFFC0CB),
+    ("plum", 0xDDA0DD),
+    ("powderblue", 0xB0E0E6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xFF0000),
+    ("rosybrown", 0xBC8F8F),
+    ("royalblue", 0x4169E1),
+    ("saddlebrown", 0x8B4513),
+    ("salmon", 0xFA8072),
+    ("sandybrown", 0xF4A460),
+    ("seagreen", 0x2E8B57),
+    ("seashell", 0xFFF5EE),
+    ("sienna", 0xA0522D),
+    ("silver", 0xC0C0C0),
+    ("skyblue", 0x87CEEB),
+    ("slateblue", 0x6A5ACD),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xFFFAFA),
+    ("springgreen", 0x00FF7F),
+    ("steelblue", 0x4682B4),
+    ("tan", 0xD2B48C),
+    ("teal", 0x008080),
+    ("thistle", 0xD8BFD8),
+    ("tomato", 0xFF6347),
+    ("turquoise", 0x40E0D0),
+    ("violet", 0xEE82EE),
+    ("wheat", 0xF5DEB3),
+    ("white", 0xFFFFFF),
+    ("whitesmoke", 0xF5F5F5),
+    ("yellow", 0xFFFF00),
+    ("yellowgreen", 0x9ACD32),
+];
+
+/// X11 colors, missing in CSS: numbered shades (e.g. "gold3", "gray50") and a few extra names
+const X11_COLOR_NAMES: [(&str, u32); 518] = [
+    ("antiquewhite1", 0xFFEFDB),
+    ("antiquewhite2", 0xEEDFCC),
+    ("antiquewhite3", 0xCDC0B0),
+    ("antiquewhite4", 0x8B8378),
+    ("aquamarine1", 0x7FFFD4),
+    ("aquamarine2", 0x76EEC6),
+    ("aquamarine3", 0x66CDAA),
+    ("aquamarine4", 0x458B74),
+    ("azure1", 0xF0FFFF),
+    ("azure2", 0xE0EEEE),
+    ("azure3", 0xC1CDCD),
+    ("azure4", 0x838B8B),
+    ("bisque1", 0xFFE4C4),
+    ("bisque2", 0xEED5B7),
+    ("bisque3", 0xCDB79E),
+    ("bisque4", 0x8B7D6B),
+    ("blue1", 0x0000FF),
+    ("blue2", 0x0000EE),
+    ("blue3", 0x0000CD),
+    ("blue4", 0x00008B),
+    ("brown1", 0xFF4040),
+    ("brown2", 0xEE3B3B),
+    ("brown3", 0xCD3333),
+    ("brown4", 0x8B2323),
+    ("burlywood1", 0xFFD39B),
+    ("burlywood2", 0xEEC591),
+    ("burlywood3", 0xCDAA7D),
+    ("burlywood4", 0x8B7355),
+    ("cadetblue1", 0x98F5FF),
+    ("cadetblue2", 0x8EE5EE),
+    ("cadetblue3", 0x7AC5CD),
+    ("cadetblue4", 0x53868B),
+    ("chartreuse1", 0x7FFF00),
+    ("chartreuse2", 0x76EE00),
+    ("chartreuse3", 0x66CD00),
+    ("chartreuse4", 0x458B00),
+    ("chocolate1", 0xFF7F24),
+    ("chocolate2", 0xEE7621),
+    ("chocolate3", 0xCD661D),
+    ("chocolate4", 0x8B4513),
+    ("coral1", 0xFF7256),
+    ("coral2", 0xEE6A50),
+    ("coral3", 0xCD5B45),
+    ("coral4", 0x8B3E2F),
+    ("cornsilk1", 0xFFF8DC),
+    ("cornsilk2", 0xEEE8CD),
+    ("cornsilk3", 0xCDC8B1),
+    ("cornsilk4", 0x8B8878),
+    ("cyan1", 0x00FFFF),
+    ("cyan2", 0x00EEEE),
+    ("cyan3", 0x00CDCD),
+    ("cyan4", 0x008B8B),
+    ("darkgoldenrod1", 0xFFB90F),
+    ("darkgoldenrod2", 0xEEAD0E),
+    ("darkgoldenrod3", 0xCD950C),
+    ("darkgoldenrod4", 0x8B6508),
+    ("darkolivegreen1", 0xCAFF70),
+    ("darkolivegreen2", 0xBCEE68),
+    ("darkolivegreen3", 0xA2CD5A),
+    ("darkolivegreen4", 0x6E8B3D),
+    ("darkorange1", 0xFF7F00),
+    ("darkorange2", 0xEE7600),
+    ("darkorange3", 0xCD6600),
+    ("darkorange4", 0x8B4500),
+    ("darkorchid1", 0xBF3EFF),
+    ("darkorchid2", 0xB23AEE),
+    ("darkorchid3", 0x9A32CD),
+    ("darkorchid4", 0x68228B),
+    ("darkseagreen1", 0xC1FFC1),
+    ("darkseagreen2", 0xB4EEB4),
+    ("darkseagreen3", 0x9BCD9B),
+    ("darkseagreen4", 0x698B69),
+    ("darkslategray1", 0x97FFFF),
+    ("darkslategray2", 0x8DEEEE),
+    ("darkslategray3", 0x79CDCD),
+    ("darkslategray4", 0x528B8B),
+    ("deeppink1", 0xFF1493),
+    ("deeppink2", 0xEE1289),
+    ("deeppink3", 0xCD1076),
+    ("deeppink4", 0x8B0A50),
+    ("deepskyblue1", 0x00BFFF),
+    ("deepskyblue2", 0x00B2EE),
+    ("deepskyblue3", 0x009ACD),
+    ("deepskyblue4", 0x00688B),
+    ("dodgerblue1", 0x1E90FF),
+    ("dodgerblue2", 0x1C86EE),
+    ("dodgerblue3", 0x1874CD),
+    ("dodgerblue4", 0x104E8B),
+    ("firebrick1", 0xFF3030),
+    ("firebrick2", 0xEE2C2C),
+    ("firebrick3", 0xCD2626),
+    ("firebrick4", 0x8B1A1A),
+    ("gold1", 0xFFD700),
+    ("gold2", 0xEEC900),
+    ("gold3", 0xCDAD00),
+    ("gold4", 0x8B7500),
+    ("goldenrod1", 0xFFC125),
+    ("goldenrod2", 0xEEB422),
+    ("goldenrod3", 0xCD9B1D),
+    ("goldenrod4", 0x8B6914),
+    ("gray0", 0x000000),
+    ("gray1", 0x030303),
+    ("gray2", 0x050505),
+    ("gray3", 0x080808),
+    ("gray4", 0x0A0A0A),
+    ("gray5", 0x0D0D0D),
+    ("gray6", 0x0F0F0F),
+    ("gray7", 0x121212),
+    ("gray8", 0x141414),
+    ("gray9", 0x171717),
+    ("gray10", 0x1A1A1A),
+    ("gray11", 0x1C1C1C),
+    ("gray12", 0x1F1F1F),
+    ("gray13", 0x212121),
+    ("gray14", 0x242424),
+    ("gray15", 0x262626),
+    ("gray16", 0x292929),
+    ("gray17", 0x2B2B2B),
+    ("gray18", 0x2E2E2E),
+    ("gray19", 0x303030),
+    ("gray20", 0x333333),
+    ("gray21", 0x363636),
+    ("gray22", 0x383838),
+    ("gray23", 0x3B3B3B),
+    ("gray24", 0x3D3D3D),
+    ("gray25", 0x404040),
+    ("gray26", 0x424242),
+    ("gray27", 0x454545),
+    ("gray28", 0x474747),
+    ("gray29", 0x4A4A4A),
+    ("gray30", 0x4D4D4D),
+    ("gray31", 0x4F4F4F),
+    ("gray32", 0x525252),
+    ("gray33", 0x545454),
+    ("gray34", 0x575757),
+    ("gray35", 0x595959),
+    ("gray36", 0x5C5C5C),
+    ("gray37", 0x5E5E5E),
+    ("gray38", 0x616161),
+    ("gray39", 0x636363),
+    ("gray40", 0x666666),
+    ("gray41", 0x696969),
+    ("gray42", 0x6B6B6B),
+    ("gray43", 0x6E6E6E),
+    ("gray44", 0x707070),
+    ("gray45", 0x737373),
+    ("gray46", 0x757575),
+    ("gray47", 0x787878),
+    ("gray48", 0x7A7A7A),
+    ("gray49", 0x7D7D7D),
+    ("gray50", 0x7F7F7F),
+    ("gray51", 0x828282),
+    ("gray52", 0x858585),
+    ("gray53", 0x878787),
+    ("gray54", 0x8A8A8A),
+    ("gray55", 0x8C8C8C),
+    ("gray56", 0x8F8F8F),
+    ("gray57", 0x919191),
+    ("gray58", 0x949494),
+    ("gray59", 0x969696),
+    ("gray60", 0x999999),
+    ("gray61", 0x9C9C9C),
+    ("gray62", 0x9E9E9E),
+    ("gray63", 0xA1A1A1),
+    ("gray64", 0xA3A3A3),
+    ("gray65", 0xA6A6A6),
+    ("gray66", 0xA8A8A8),
+    ("gray67", 0xABABAB),
+    ("gray68", 0xADADAD),
+    ("gray69", 0xB0B0B0),
+    ("gray70", 0xB3B3B3),
+    ("gray71", 0xB5B5B5),
+    ("gray72", 0xB8B8B8),
+    ("gray73", 0xBABABA),
+    ("gray74", 0xBDBDBD),
+    ("gray75", 0xBFBFBF),
+    ("gray76", 0xC2C2C2),
+    ("gray77", 0xC4C4C4),
+    ("gray78", 0xC7C7C7),
+    ("gray79", 0xC9C9C9),
+    ("gray80", 0xCCCCCC),
+    ("gray81", 0xCFCFCF),
+    ("gray82", 0xD1D1D1),
+    ("gray83", 0xD4D4D4),
+    ("gray84", 0xD6D6D6),
+    ("gray85", 0xD9D9D9),
+    ("gray86", 0xDBDBDB),
+    ("gray87", 0xDEDEDE),
+    ("gray88", 0xE0E0E0),
+    ("gray89", 0xE3E3E3),
+    ("gray90", 0xE5E5E5),
+    ("gray91", 0xE8E8E8),
+    ("gray92", 0xEBEBEB),
+    ("gray93", 0xEDEDED),
+    ("gray94", 0xF0F0F0),
+    ("gray95", 0xF2F2F2),
+    ("gray96", 0xF5F5F5),
+    ("gray97", 0xF7F7F7),
+    ("gray98", 0xFAFAFA),
+    ("gray99", 0xFCFCFC),
+    ("gray100", 0xFFFFFF),
+    ("green1", 0x00FF00),
+    ("green2", 0x00EE00),
+    ("green3", 0x00CD00),
+    ("green4", 0x008B00),
+    ("grey0", 0x000000),
+    ("grey1", 0x030303),
+    ("grey2", 0x050505),
+    ("grey3", 0x080808),
+    ("grey4", 0x0A0A0A),
+    ("grey5", 0x0D0D0D),
+    ("grey6", 0x0F0F0F),
+    ("grey7", 0x121212),
+    ("grey8", 0x141414),
+    ("grey9", 0x171717),
+    ("grey10", 0x1A1A1A),
+    ("grey11", 0x1C1C1C),
+    ("grey12", 0x1F1F1F),
+    ("grey13", 0x212121),
+    ("grey14", 0x242424),
+    ("grey15", 0x262626),
+    ("grey16", 0x292929),
+    ("grey17", 0x2B2B2B),
+    ("grey18", 0x2E2E2E),
+    ("grey19", 0x303030),
+    ("grey20", 0x333333),
+    ("grey21", 0x363636),
+    ("grey22", 0x383838),
+    ("grey23", 0x3B3B3B),
+    ("grey24", 0x3D3D3D),
+    ("grey25", 0x404040),
+    ("grey26", 0x424242),
+    ("grey27", 0x454545),
+    ("grey28", 0x474747),
+    ("grey29", 0x4A4A4A),
+    ("grey30", 0x4D4D4D),
+    ("grey31", 0x4F4F4F),
+    ("grey32", 0x525252),
+    ("grey33", 0x545454),
+    ("grey34", 0x575757),
+    ("grey35", 0x595959),
+    ("grey36", 0x5C5C5C),
+    ("grey37", 0x5E5E5E),
+    ("grey38", 0x616161),
+    ("grey39", 0x636363),
+    ("grey40", 0x666666),
+    ("grey41", 0x696969),
+    ("grey42", 0x6B6B6B),
+    ("grey43", 0x6E6E6E),
+    ("grey44", 0x707070),
+    ("grey45", 0x737373),
+    ("grey46", 0x757575),
+    ("grey47", 0x787878),
+    ("grey48", 0x7A7A7A),
+    ("grey49", 0x7D7D7D),
+    ("grey50", 0x7F7F7F),
+    ("grey51", 0x828282),
+    ("grey52", 0x858585),
+    ("grey53", 0x878787),
+    ("grey54", 0x8A8A8A),
+    ("grey55", 0x8C8C8C),
+    ("grey56", 0x8F8F8F),
+    ("grey57", 0x919191),
+    ("grey58", 0x949494),
+    ("grey59", 0x969696),
+    ("grey60", 0x999999),
+    ("grey61", 0x9C9C9C),
+    ("grey62", 0x9E9E9E),
+    ("grey63", 0xA1A1A1),
+    ("grey64", 0xA3A3A3),
+    ("grey65", 0xA6A6A6),
+    ("grey66", 0xA8A8A8),
+    ("grey67", 0xABABAB),
+    ("grey68", 0xADADAD),
+    ("grey69", 0xB0B0B0),
+    ("grey70", 0xB3B3B3),
+    ("grey71", 0xB5B5B5),
+    ("grey72", 0xB8B8B8),
+    ("grey73", 0xBABABA),
+    ("grey74", 0xBDBDBD),
+    ("grey75", 0xBFBFBF),
+    ("grey76", 0xC2C2C2),
+    ("grey77", 0xC4C4C4),
+    ("grey78", 0xC7C7C7),
+    ("grey79", 0xC9C9C9),
+    ("grey80", 0xCCCCCC),
+    ("grey81", 0xCFCFCF),
+    ("grey82", 0xD1D1D1),
+    ("grey83", 0xD4D4D4),
+    ("grey84", 0xD6D6D6),
+    ("grey85", 0xD9D9D9),
+    ("grey86", 0xDBDBDB),
+    ("grey87", 0xDEDEDE),
+    ("grey88", 0xE0E0E0),
+    ("grey89", 0xE3E3E3),
+    ("grey90", 0xE5E5E5),
+    ("grey91", 0xE8E8E8),
+    ("grey92", 0xEBEBEB),
+    ("grey93", 0xEDEDED),
+    ("grey94", 0xF0F0F0),
+    ("grey95", 0xF2F2F2),
+    ("grey96", 0xF5F5F5),
+    ("grey97", 0xF7F7F7),
+    ("grey98", 0xFAFAFA),
+    ("grey99", 0xFCFCFC),
+    ("grey100", 0xFFFFFF),
+    ("honeydew1", 0xF0FFF0),
+    ("honeydew2", 0xE0EEE0),
+    ("honeydew3", 0xC1CDC1),
+    ("honeydew4", 0x838B83),
+    ("hotpink1", 0xFF6EB4),
+    ("hotpink2", 0xEE6AA7),
+    ("hotpink3", 0xCD6090),
+    ("hotpink4", 0x8B3A62),
+    ("indianred1", 0xFF6A6A),
+    ("indianred2", 0xEE6363),
+    ("indianred3", 0xCD5555),
+    ("indianred4", 0x8B3A3A),
+    ("ivory1", 0xFFFFF0),
+    ("ivory2", 0xEEEEE0),
+    ("ivory3", 0xCDCDC1),
+    ("ivory4", 0x8B8B83),
+    ("khaki1", 0xFFF68F),
+    ("khaki2", 0xEEE685),
+    ("khaki3", 0xCDC673),
+    ("khaki4", 0x8B864E),
+    ("lavenderblush1", 0xFFF0F5),
+    ("lavenderblush2", 0xEEE0E5),
+    ("lavenderblush3", 0xCDC1C5),
+    ("lavenderblush4", 0x8B8386),
+    ("lemonchiffon1", 0xFFFACD),
+    ("lemonchiffon2", 0xEEE9BF),
+    ("lemonchiffon3", 0xCDC9A5),
+    ("lemonchiffon4", 0x8B8970),
+    ("lightblue1", 0xBFEFFF),
+    ("lightblue2", 0xB2DFEE),
+    ("lightblue3", 0x9AC0CD),
+    ("lightblue4", 0x68838B),
+    ("lightcyan1", 0xE0FFFF),
+    ("lightcyan2", 0xD1EEEE),
+    ("lightcyan3", 0xB4CDCD),
+    ("lightcyan4", 0x7A8B8B),
+    ("lightgoldenrod", 0xEEDD82),
+    ("lightgoldenrod1", 0xFFEC8B),
+    ("lightgoldenrod2", 0xEEDC82),
+    ("lightgoldenrod3", 0xCDBE70),
+    ("lightgoldenrod4", 0x8B814C),
+    ("lightpink1", 0xFFAEB9),
+    ("lightpink2", 0xEEA2AD),
+    ("lightpink3", 0xCD8C95),
+    ("lightpink4", 0x8B5F65),
+    ("lightsalmon1", 0xFFA07A),
+    ("lightsalmon2", 0xEE9572),
+    ("lightsalmon3", 0xCD8162),
+    ("lightsalmon4", 0x8B5742),
+    ("lightskyblue1", 0xB0E2FF),
+    ("lightskyblue2", 0xA4D3EE),
+    ("lightskyblue3", 0x8DB6CD),
+    ("lightskyblue4", 0x607B8B),
+    ("lightslateblue", 0x8470FF),
+    ("lightsteelblue1", 0xCAE1FF),
+    ("lightsteelblue2", 0xBCD2EE),
+    ("lightsteelblue3", 0xA2B5CD),
+    ("lightsteelblue4", 0x6E7B8B),
+    ("lightyellow1", 0xFFFFE0),
+    ("lightyellow2", 0xEEEED1),
+    ("lightyellow3", 0xCDCDB4),
+    ("lightyellow4", 0x8B8B7A),
+    ("magenta1", 0xFF00FF),
+    ("magenta2", 0xEE00EE),
+    ("magenta3", 0xCD00CD),
+    ("magenta4", 0x8B008B),
+    ("maroon1", 0xFF34B3),
+    ("maroon2", 0xEE30A7),
+    ("maroon3", 0xCD2990),
+    ("maroon4", 0x8B1C62),
+    ("mediumorchid1", 0xE066FF),
+    ("mediumorchid2", 0xD15FEE),
+    ("mediumorchid3", 0xB452CD),
+    ("mediumorchid4", 0x7A378B),
+    ("mediumpurple1", 0xAB82FF),
+    ("mediumpurple2", 0x9F79EE),
+    ("mediumpurple3", 0x8968CD),
+    ("mediumpurple4", 0x5D478B),
+    ("mistyrose1", 0xFFE4E1),
+    ("mistyrose2", 0xEED5D2),
+    ("mistyrose3", 0xCDB7B5),
+    ("mistyrose4", 0x8B7D7B),
+    ("navajowhite1", 0xFFDEAD),
+    ("navajowhite2", 0xEECFA1),
+    ("navajowhite3", 0xCDB38B),
+    ("navajowhite4", 0x8B795E),
+    ("navyblue", 0x000080),
+    ("olivedrab1", 0xC0FF3E),
+    ("olivedrab2", 0xB3EE3A),
+    ("olivedrab3", 0x9ACD32),
+    ("olivedrab4", 0x698B22),
+    ("orange1", 0xFFA500),
+    ("orange2", 0xEE9A00),
+    ("orange3", 0xCD8500),
+    ("orange4", 0x8B5A00),
+    ("orangered1", 0xFF4500),
+    ("orangered2", 0xEE4000),
+    ("orangered3", 0xCD3700),
+    ("orangered4", 0x8B2500),
+    ("orchid1", 0xFF83FA),
+    ("orchid2", 0xEE7AE9),
+    ("orchid3", 0xCD69C9),
+    ("orchid4", 0x8B4789),
+    ("palegreen1", 0x9AFF9A),
+    ("palegreen2", 0x90EE90),
+    ("palegreen3", 0x7CCD7C),
+    ("palegreen4", 0x548B54),
+    ("paleturquoise1", 0xBBFFFF),
+    ("paleturquoise2", 0xAEEEEE),
+    ("paleturquoise3", 0x96CDCD),
+    ("paleturquoise4", 0x668B8B),
+    ("palevioletred1", 0xFF82AB),
+    ("palevioletred2", 0xEE799F),
+    ("palevioletred3", 0xCD6889),
+    ("palevioletred4", 0x8B475D),
+    ("peachpuff1", 0xFFDAB9),
+    ("peachpuff2", 0xEECBAD),
+    ("peachpuff3", 0xCDAF95),
+    ("peachpuff4", 0x8B7765),
+    ("pink1", 0xFFB5C5),
+    ("pink2", 0xEEA9B8),
+    ("pink3", 0xCD919E),
+    ("pink4", 0x8B636C),
+    ("plum1", 0xFFBBFF),
+    ("plum2", 0xEEAEEE),
+    ("plum3", 0xCD96CD),
+    ("plum4", 0x8B668B),
+    ("purple1", 0x9B30FF),
+    ("purple2", 0x912CEE),
+    ("purple3", 0x7D26CD),
+    ("purple4", 0x551A8B),
+    ("red1", 0xFF0000),
+    ("red2", 0xEE0000),
+    ("red3", 0xCD0000),
+    ("red4", 0x8B0000),
+    ("rosybrown1", 0xFFC1C1),
+    ("rosybrown2", 0xEEB4B4),
+    ("rosybrown3", 0xCD9B9B),
+    ("rosybrown4", 0x8B6969),
+    ("royalblue1", 0x4876FF),
+    ("royalblue2", 0x436EEE),
+    ("royalblue3", 0x3A5FCD),
+    ("royalblue4", 0x27408B),
+    ("salmon1", 0xFF8C69),
+    ("salmon2", 0xEE8262),
+    ("salmon3", 0xCD7054),
+    ("salmon4", 0x8B4C39),
+    ("seagreen1", 0x54FF9F),
+    ("seagreen2", 0x4EEE94),
+    ("seagreen3", 0x43CD80),
+    ("seagreen4", 0x2E8B57),
+    ("seashell1", 0xFFF5EE),
+    ("seashell2", 0xEEE5DE),
+    ("seashell3", 0xCDC5BF),
+    ("seashell4", 0x8B8682),
+    ("sienna1", 0xFF8247),
+    ("sienna2", 0xEE7942),
+    ("sienna3", 0xCD6839),
+    ("sienna4", 0x8B4726),
+    ("skyblue1", 0x87CEFF),
+    ("skyblue2", 0x7EC0EE),
+    ("skyblue3", 0x6CA6CD),
+    ("skyblue4", 0x4A708B),
+    ("slateblue1", 0x836FFF),
+    ("slateblue2", 0x7A67EE),
+    ("slateblue3", 0x6959CD),
+    ("slateblue4", 0x473C8B),
+    ("slategray1", 0xC6E2FF),
+    ("slategray2", 0xB9D3EE),
+    ("slategray3", 0x9FB6CD),
+    ("slategray4", 0x6C7B8B),
+    ("snow1", 0xFFFAFA),
+    ("snow2", 0xEEE9E9),
+    ("snow3", 0xCDC9C9),
+    ("snow4", 0x8B8989),
+    ("springgreen1", 0x00FF7F),
+    ("springgreen2", 0x00EE76),
+    ("springgreen3", 0x00CD66),
+    ("springgreen4", 0x008B45),
+    ("steelblue1", 0x63B8FF),
+    ("steelblue2", 0x5CACEE),
+    ("steelblue3", 0x4F94CD),
+    ("steelblue4", 0x36648B),
+    ("tan1", 0xFFA54F),
+    ("tan2", 0xEE9A49),
+    ("tan3", 0xCD853F),
+    ("tan4", 0x8B5A2B),
+    ("thistle1", 0xFFE1FF),
+    ("thistle2", 0xEED2EE),
+    ("thistle3", 0xCDB5CD),
+    ("thistle4", 0x8B7B8B),
+    ("tomato1", 0xFF6347),
+    ("tomato2", 0xEE5C42),
+    ("tomato3", 0xCD4F39),
+    ("tomato4", 0x8B3626),
+    ("turquoise1", 0x00F5FF),
+    ("turquoise2", 0x00E5EE),
+    ("turquoise3", 0x00C5CD),
+    ("turquoise4", 0x00868B),
+    ("violetred", 0xD02090),
+    ("violetred1", 0xFF3E96),
+    ("violetred2", 0xEE3A8C),
+    ("violetred3", 0xCD3278),
+    ("violetred4", 0x8B2252),
+    ("wheat1", 0xFFE7BA),
+    ("wheat2", 0xEED8AE),
+    ("wheat3", 0xCDBA96),
+    ("wheat4", 0x8B7E66),
+    ("yellow1", 0xFFFF00),
+    ("yellow2", 0xEEEE00),
+    ("yellow3", 0xCDCD00),
+    ("yellow4", 0x8B8B00),
+];
+
+/// Hue (degrees, 0-360), saturation and value (0-1)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Hsv {
+    pub hue: f32,
+    pub saturation: f32,
+    pub value: f32,
+}
+
+/// Hue (degrees, 0-360), saturation and lightness (0-1)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Hsl {
+    pub hue: f32,
+    pub saturation: f32,
+    pub lightness: f32,
+}
+
+/// sRGB without gamma encoding (proportional to the light intensity), components 0-1
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LinearRgb {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+}
+
+/// OKLab perceptual color space: lightness (0-1) and a, b (green-red and blue-yellow axes,
+/// about -0.4..0.4)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OkLab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+fn to_u8(v: f32) -> u8 {
+    (v.clamp(0., 1.) * 255.).round() as u8
+}
+
+/// HSV to RGB components (0-1)
+fn hsv_components(hue: f32, saturation: f32, value: f32) -> (f32, f32, f32) {
+    let h = hue.rem_euclid(360.) / 60.;
+    let (s, v) = (saturation.clamp(0., 1.), value.clamp(0., 1.));
+    let f = h - h.floor();
+    let p = v * (1. - s);
+    let q = v * (1. - s * f);
+    let t = v * (1. - s * (1. - f));
+    match h as u32 % 6 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    }
+}
+
+fn srgb_decode(c: u8) -> f32 {
+    let c = c as f32 / 255.;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn srgb_encode(c: f32) -> u8 {
+    let c = c.clamp(0., 1.);
+    to_u8(if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    })
+}
+
+impl RGBColor {
+    fn components(&self) -> (f32, f32, f32) {
+        (
+            self.red as f32 / 255.,
+            self.green as f32 / 255.,
+            self.blue as f32 / 255.,
+        )
+    }
+
+    /// Hue of the color, degrees. Returns 0 for grays
+    fn hue(&self, max: f32, delta: f32) -> f32 {
+        let (r, g, b) = self.components();
+        if delta == 0. {
+            0.
+        } else if max == r {
+            (60. * (g - b) / delta).rem_euclid(360.)
+        } else if max == g {
+            60. * (b - r) / delta + 120.
+        } else {
+            60. * (r - g) / delta + 240.
+        }
+    }
+
+    pub fn to_hsv(&self) -> Hsv {
+        let (r, g, b) = self.components();
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+        Hsv {
+            hue: self.hue(max, delta),
+            saturation: if max == 0. { 0. } else { delta / max },
+            value: max,
+        }
+    }
+
+    pub fn from_hsv(hsv: Hsv) -> Self {
+        let (r, g, b) = hsv_components(hsv.hue, hsv.saturation, hsv.value);
+        Self::new(to_u8(r), to_u8(g), to_u8(b))
+    }
+
+    pub fn to_hsl(&self) -> Hsl {
+        let (r, g, b) = self.components();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+        let lightness = (max + min) / 2.;
+        Hsl {
+            hue: self.hue(max, delta),
+            saturation: if delta == 0. {
+                0.
+            } else {
+                delta / (1. - (2. * lightness - 1.).abs())
+            },
+            lightness,
+        }
+    }
+
+    pub fn from_hsl(hsl: Hsl) -> Self {
+        let l = hsl.lightness.clamp(0., 1.);
+        let value = l + hsl.saturation.clamp(0., 1.) * l.min(1. - l);
+        Self::from_hsv(Hsv {
+            hue: hsl.hue,
+            saturation: if value == 0. {
+                0.
+            } else {
+                2. * (1. - l / value)
+            },
+            value,
+        })
+    }
+
+    pub fn to_linear(&self) -> LinearRgb {
+        LinearRgb {
+            red: srgb_decode(self.red),
+            green: srgb_decode(self.green),
+            blue: srgb_decode(self.blue),
+        }
+    }
+
+    pub fn from_linear(rgb: LinearRgb) -> Self {
+        Self::new(
+            srgb_encode(rgb.red),
+            srgb_encode(rgb.green),
+            srgb_encode(rgb.blue),
+        )
+    }
+
+    pub fn to_oklab(&self) -> OkLab {
+        let LinearRgb { red, green, blue } = self.to_linear();
+        let l = (0.412_221_46 * red + 0.536_332_55 * green + 0.051_445_995 * blue).cbrt();
+        let m = (0.211_903_5 * red + 0.680_699_5 * green + 0.107_396_96 * blue).cbrt();
+        let s = (0.088_302_46 * red + 0.281_718_85 * green + 0.629_978_7 * blue).cbrt();
+        OkLab {
+            l: 0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+            a: 1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+            b: 0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+        }
+    }
+
+    pub fn from_oklab(lab: OkLab) -> Self {
+        let l = (lab.l + 0.396_337_78 * lab.a + 0.215_803_76 * lab.b).powi(3);
+        let m = (lab.l - 0.105_561_346 * lab.a - 0.063_854_17 * lab.b).powi(3);
+        let s = (lab.l - 0.089_484_18 * lab.a - 1.291_485_5 * lab.b).powi(3);
+        Self::from_linear(LinearRgb {
+            red: 4.076_741_7 * l - 3.307_711_6 * m + 0.230_969_94 * s,
+            green: -1.268_438 * l + 2.609_757_4 * m - 0.341_319_38 * s,
+            blue: -0.004_196_086_3 * l - 0.703_418_6 * m + 1.707_614_7 * s,
+        })
+    }
+
+    /// Color of the black body at the temperature (1000-40000K, clamped), Tanner Helland's
+    /// approximation
+    pub fn from_kelvin(kelvin: f32) -> Self {
+        let t = kelvin.clamp(1000., 40000.) / 100.;
+        let c = |v: f32| v.clamp(0., 255.).round() as u8;
+        let red = if t <= 66. {
+            255.
+        } else {
+            329.698_73 * (t - 60.).powf(-0.133_204_76)
+        };
+        let green = if t <= 66. {
+            99.470_8 * t.ln() - 161.119_57
+        } else {
+            288.122_17 * (t - 60.).powf(-0.075_514_85)
+        };
+        let blue = if t >= 66. {
+            255.
+        } else if t <= 19. {
+            0.
+        } else {
+            138.517_73 * (t - 10.).ln() - 305.044_8
+        };
+        Self::new(c(red), c(green), c(blue))
+    }
+
+    /// Scales the brightness. The factor is applied in linear light, so hue and saturation are
+    /// kept
+    pub fn with_brightness(&self, factor: f32) -> Self {
+        let LinearRgb { red, green, blue } = self.to_linear();
+        let factor = factor.max(0.);
+        Self::from_linear(LinearRgb {
+            red: red * factor,
+            green: green * factor,
+            blue: blue * factor,
+        })
+    }
+}
+
+impl From<u32> for RGBColor {
+    /// Converts 0xRRGGBB value
+    fn from(v: u32) -> Self {
+        Self::new((v >> 16) as u8, (v >> 8) as u8, v as u8)
+    }
+}
+
+impl From<RGBColor> for u32 {
+    fn from(c: RGBColor) -> Self {
+        (c.red as u32) << 16 | (c.green as u32) << 8 | c.blue as u32
+    }
+}
+
+impl std::str::FromStr for RGBColor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| Error::InvalidValue(format!("color {:?}: {}", s, reason));
+        let value = s.trim().to_lowercase();
+        if let Some(hex) = value.strip_prefix('#') {
+            return parse_hex(hex, true).ok_or_else(|| invalid("invalid hex color"));
+        }
+        if let Some(args) = function_args(&value, "rgb") {
+            return parse_rgb(&args?).ok_or_else(|| invalid("invalid rgb() components"));
+        }
+        if let Some(args) = function_args(&value, "hsl") {
+            return parse_hsl(&args?).ok_or_else(|| invalid("invalid hsl() components"));
+        }
+        if let Some(c) = parse_hex(&value, false) {
+            return Ok(c);
+        }
+        let name: String = value
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '_' && *c != '-')
+            .collect();
+        COLOR_NAMES
+            .iter()
+            .chain(X11_COLOR_NAMES.iter())
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| (*v).into())
+            .ok_or_else(|| invalid("unknown color"))
+    }
+}
+
+fn parse_hex(hex: &str, allow_short: bool) -> Option<RGBColor> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let v = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        6 => Some(v.into()),
+        3 if allow_short => {
+            // each digit is doubled: #abc = #aabbcc
+            let d = |shift: u32| ((v >> shift) & 0xF) as u8 * 0x11;
+            Some(RGBColor::new(d(8), d(4), d(0)))
+        }
+        _ => None,
+    }
+}
+
+/// Returns None if the value is not the function call, or the arguments, split by commas or
+/// whitespaces
+fn function_args(value: &str, name: &str) -> Option<Result<Vec<String>, Error>> {
+    let args = value.strip_prefix(name)?.trim_start().strip_prefix('(')?;
+    Some(match args.strip_suffix(')') {
+        Some(args) => Ok(args
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|v| !v.is_empty())
+            .map(ToOwned::to_owned)
+            .collect()),
+        None => Err(Error::InvalidValue(format!(
+            "color {:?}: no closing parenthesis",
+            value
+        ))),
+    })
+}
+
+/// Parses the number or percents, returns the value in 0..=1 range, numbers are divided by max
+fn parse_component(s: &str, max: f32) -> Option<f32> {
+    let v = match s.strip_suffix('%') {
+        Some(p) => p.parse::<f32>().ok()? / 100.,
+        None => s.parse::<f32>().ok()? / max,
+    };
+    if (0. ..=1.).contains(&v) {
+        Some(v)
+    } else {
+        None
+    }
+}
+
+fn parse_rgb(args: &[String]) -> Option<RGBColor> {
+    if args.len() != 3 {
+        return None;
+    }
+    let c = |i: usize| parse_component(&args[i], 255.).map(|v| (v * 255.).round() as u8);
+    Some(RGBColor::new(c(0)?, c(1)?, c(2)?))
+}
+
+fn parse_hsl(args: &[String]) -> Option<RGBColor> {
+    if args.len() != 3 {
+        return None;
+    }
+    let h: f32 = args[0]
+        .strip_suffix("deg")
+        .unwrap_or(&args[0])
+        .parse()
+        .ok()?;
+    if !h.is_finite() {
+        return None;
+    }
+    let s = parse_component(&args[1], 100.)?;
+    let l = parse_component(&args[2], 100.)?;
+    Some(RGBColor::from_hsl(Hsl {
+        hue: h,
+        saturation: s,
+        lightness: l,
+    }))
+}
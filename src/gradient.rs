@@ -0,0 +1,247 @@
+//! Multi-stop color gradients
+//!
+//! The text form is a comma separated list of stops (position in percents and color) and
+//! options (interpolation space: rgb, hsv or oklab, hue path: shortest or longest), e.g.
+//!
+//! ```text
+//! 0:0000FF,50:00FF00,100:FF0000,hsv,longest
+//! ```
+use crate::color::{Hsv, OkLab};
+use crate::{Error, RGBColor};
+use std::fmt;
+
+/// The color space, the colors are interpolated in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    Rgb,
+    Hsv,
+    /// Perceptually uniform, no hue shifts and dark bands between the stops
+    OkLab,
+}
+
+/// The direction of hue interpolation in hue-based color spaces (HSV)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HuePath {
+    Shortest,
+    /// Goes around the color circle the other way, e.g. blue to red through green
+    Longest,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    stops: Vec<(f32, RGBColor)>,
+    space: ColorSpace,
+    hue_path: HuePath,
+}
+
+impl Gradient {
+    /// Creates a gradient, interpolated in RGB. The stop positions are percents, the stops are
+    /// sorted by position
+    pub fn new(stops: &[(f32, RGBColor)]) -> Result<Self, Error> {
+        if stops.is_empty() {
+            return Err(Error::InvalidValue("gradient: no stops".to_owned()));
+        }
+        if let Some((pos, _)) = stops.iter().find(|(pos, _)| !(0. ..=100.).contains(pos)) {
+            return Err(Error::InvalidValue(format!(
+                "gradient: stop position {} out of range 0-100",
+                pos
+            )));
+        }
+        let mut stops = stops.to_vec();
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Ok(Self {
+            stops,
+            space: ColorSpace::Rgb,
+            hue_path: HuePath::Shortest,
+        })
+    }
+
+    /// The color circle from dark violet (0%) to red (100%), the default CPU load colors
+    pub fn rainbow() -> Self {
+        let color = |hue| {
+            RGBColor::from_hsv(Hsv {
+                hue,
+                saturation: 1.,
+                value: 235. / 255.,
+            })
+        };
+        Self::new(&[(0., color(266.87)), (100., color(0.))])
+            .unwrap()
+            .with_space(ColorSpace::Hsv)
+            .with_hue_path(HuePath::Longest)
+    }
+
+    pub fn with_space(mut self, space: ColorSpace) -> Self {
+        self.space = space;
+        self
+    }
+
+    pub fn with_hue_path(mut self, hue_path: HuePath) -> Self {
+        self.hue_path = hue_path;
+        self
+    }
+
+    pub fn stops(&self) -> &[(f32, RGBColor)] {
+        &self.stops
+    }
+
+    pub fn space(&self) -> ColorSpace {
+        self.space
+    }
+
+    pub fn hue_path(&self) -> HuePath {
+        self.hue_path
+    }
+
+    /// The color at the position (percents). Positions outside of the stops get the color of
+    /// the nearest stop
+    pub fn color_at(&self, position: f32) -> RGBColor {
+        let (first, last) = (self.stops[0], self.stops[self.stops.len() - 1]);
+        if position <= first.0 {
+            return first.1;
+        }
+        if position >= last.0 {
+            return last.1;
+        }
+        let i = self.stops.iter().position(|(p, _)| *p > position).unwrap();
+        let ((p1, c1), (p2, c2)) = (self.stops[i - 1], self.stops[i]);
+        interpolate(
+            &c1,
+            &c2,
+            (position - p1) / (p2 - p1),
+            self.space,
+            self.hue_path,
+        )
+    }
+}
+
+/// Interpolates between two colors, t = 0..1
+pub fn interpolate(
+    a: &RGBColor,
+    b: &RGBColor,
+    t: f32,
+    space: ColorSpace,
+    hue_path: HuePath,
+) -> RGBColor {
+    let t = t.clamp(0., 1.);
+    let lerp = |x: f32, y: f32| x + (y - x) * t;
+    match space {
+        ColorSpace::Rgb => {
+            let c = |x: u8, y: u8| lerp(x as f32, y as f32).round() as u8;
+            RGBColor::new(c(a.red, b.red), c(a.green, b.green), c(a.blue, b.blue))
+        }
+        ColorSpace::Hsv => {
+            let (mut x, mut y) = (a.to_hsv(), b.to_hsv());
+            // grays have no hue, keep the hue of the other color
+            if x.saturation == 0. {
+                x.hue = y.hue;
+            } else if y.saturation == 0. {
+                y.hue = x.hue;
+            }
+            let mut diff = y.hue - x.hue;
+            match hue_path {
+                HuePath::Shortest if diff > 180. => diff -= 360.,
+                HuePath::Shortest if diff < -180. => diff += 360.,
+                HuePath::Longest if diff > 0. && diff < 180. => diff -= 360.,
+                HuePath::Longest if diff < 0. && diff > -180. => diff += 360.,
+                _ => {}
+            }
+            RGBColor::from_hsv(Hsv {
+                hue: x.hue + diff * t,
+                saturation: lerp(x.saturation, y.saturation),
+                value: lerp(x.value, y.value),
+            })
+        }
+        ColorSpace::OkLab => {
+            let (x, y) = (a.to_oklab(), b.to_oklab());
+            RGBColor::from_oklab(OkLab {
+                l: lerp(x.l, y.l),
+                a: lerp(x.a, y.a),
+                b: lerp(x.b, y.b),
+            })
+        }
+    }
+}
+
+/// Splits the string by commas outside of parentheses
+fn split_items(s: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, ch) in s.char_indices() {
+        match ch {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&s[start..]);
+    items
+}
+
+impl std::str::FromStr for Gradient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut stops = Vec::new();
+        let mut space = ColorSpace::Rgb;
+        let mut hue_path = HuePath::Shortest;
+        for item in split_items(s).into_iter().map(str::trim) {
+            if let Some((pos, color)) = item.split_once(':') {
+                let pos = pos.trim();
+                stops.push((
+                    pos.strip_suffix('%').unwrap_or(pos).parse().map_err(|e| {
+                        Error::InvalidValue(format!("gradient stop position {:?}: {}", pos, e))
+                    })?,
+                    color.parse()?,
+                ));
+                continue;
+            }
+            match item.to_lowercase().as_str() {
+                "rgb" => space = ColorSpace::Rgb,
+                "hsv" => space = ColorSpace::Hsv,
+                "oklab" => space = ColorSpace::OkLab,
+                "shortest" => hue_path = HuePath::Shortest,
+                "longest" => hue_path = HuePath::Longest,
+                _ => {
+                    return Err(Error::InvalidValue(format!(
+                        "gradient: unknown option {:?}",
+                        item
+                    )))
+                }
+            }
+        }
+        Ok(Self::new(&stops)?.with_space(space).with_hue_path(hue_path))
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorSpace::Rgb => write!(f, "rgb"),
+            ColorSpace::Hsv => write!(f, "hsv"),
+            ColorSpace::OkLab => write!(f, "oklab"),
+        }
+    }
+}
+
+impl fmt::Display for HuePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HuePath::Shortest => write!(f, "shortest"),
+            HuePath::Longest => write!(f, "longest"),
+        }
+    }
+}
+
+impl fmt::Display for Gradient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (pos, color) in &self.stops {
+            write!(f, "{}:{},", pos, color)?;
+        }
+        write!(f, "{},{}", self.space, self.hue_path)
+    }
+}
@@ -0,0 +1,265 @@
+//! Cloneable, thread-safe client handle
+//!
+//! The client is owned by a worker thread, requests from all handle clones are sent to it over a
+//! channel and executed one by one. Every request has its own timeout: the caller waits for the
+//! result no longer than the timeout, the requests, expired while queued, are not executed
+use crate::selector::{LedSelector, Selector};
+use crate::{ControllerData, DeviceType, Error, ModeData, Notification, OpenRGBClient, RGBColor};
+use log::debug;
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default request timeout, the worker may need several I/O timeouts to complete a request
+/// (reconnects, retries)
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Command {
+    deadline: Instant,
+    f: Box<dyn FnOnce(&mut OpenRGBClient) + Send>,
+}
+
+#[derive(Clone)]
+pub struct ClientHandle {
+    tx: mpsc::Sender<Command>,
+    timeout: Duration,
+}
+
+fn worker(mut client: OpenRGBClient, rx: mpsc::Receiver<Command>) {
+    let io_timeout = client.timeout;
+    // stops when all handles are dropped
+    for cmd in rx {
+        let now = Instant::now();
+        if now >= cmd.deadline {
+            debug!("request expired in the queue");
+            continue;
+        }
+        client.set_timeout(std::cmp::min(io_timeout, cmd.deadline - now));
+        (cmd.f)(&mut client);
+    }
+    debug!("client worker stopped");
+}
+
+impl ClientHandle {
+    /// Moves the client to a new worker thread
+    pub fn new(client: OpenRGBClient) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || worker(client, rx));
+        Self {
+            tx,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+        }
+    }
+
+    /// Creates a handle for the same client with another default request timeout
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            timeout,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Executes the closure with the client on the worker thread, using the default timeout
+    pub fn call<R, F>(&self, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut OpenRGBClient) -> Result<R, Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.call_timeout(self.timeout, f)
+    }
+
+    /// Executes the closure with the client on the worker thread. Returns TimedOut I/O error if
+    /// the result is not received in time
+    pub fn call_timeout<R, F>(&self, timeout: Duration, f: F) -> Result<R, Error>
+    where
+        F: FnOnce(&mut OpenRGBClient) -> Result<R, Error> + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.tx
+            .send(Command {
+                deadline: Instant::now() + timeout,
+                f: Box::new(move |client| {
+                    let _ = tx.send(f(client));
+                }),
+            })
+            .map_err(|_| worker_stopped())?;
+        match rx.recv_timeout(timeout) {
+            Ok(v) => v,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(Error::Io(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request timed out",
+            ))),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(worker_stopped()),
+        }
+    }
+
+    pub fn set_path(&self, path: &str) -> Result<(), Error> {
+        let path = path.to_owned();
+        self.call(move |c| {
+            c.set_path(&path);
+            Ok(())
+        })
+    }
+
+    pub fn load(&self) -> Result<(), Error> {
+        self.call(OpenRGBClient::load)
+    }
+
+    pub fn reload(&self) -> Result<(), Error> {
+        self.call(OpenRGBClient::reload)
+    }
+
+    /// Controllers, loaded from the server
+    pub fn controllers(&self) -> Result<Vec<ControllerData>, Error> {
+        self.call(|c| Ok(c.controllers.clone()))
+    }
+
+    /// The negotiated protocol version
+    pub fn protocol(&self) -> Result<Option<u32>, Error> {
+        self.call(|c| Ok(c.protocol))
+    }
+
+    pub fn poll_notifications(&self) -> Result<Vec<Notification>, Error> {
+        self.call(OpenRGBClient::poll_notifications)
+    }
+
+    pub fn set_color_by_id(&self, controller_id: u32, color: &RGBColor) -> Result<(), Error> {
+        let color = *color;
+        self.call(move |c| c.set_color_by_id(controller_id, &color))
+    }
+
+    /// Sets LED colors of the controller, the frame is produced by the closure
+    pub fn set_colors_by_id<F>(&self, controller_id: u32, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor> + Send + 'static,
+    {
+        self.call(move |c| c.set_colors_by_id(controller_id, f))
+    }
+
+    pub fn set_color_by_name(&self, controller_name: &str, color: &RGBColor) -> Result<(), Error> {
+        let (name, color) = (controller_name.to_owned(), *color);
+        self.call(move |c| c.set_color_by_name(&name, &color))
+    }
+
+    pub fn set_color_by_device_types(
+        &self,
+        device_types: &[DeviceType],
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        let (device_types, color) = (device_types.to_vec(), *color);
+        self.call(move |c| c.set_color_by_device_types(&device_types, &color))
+    }
+
+    pub fn set_color_by_device_type(
+        &self,
+        device_type: DeviceType,
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        let color = *color;
+        self.call(move |c| c.set_color_by_device_type(device_type, &color))
+    }
+
+    pub fn set_color_by_selector(
+        &self,
+        selector: &Selector,
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        let (selector, color) = (selector.clone(), *color);
+        self.call(move |c| c.set_color_by_selector(&selector, &color))
+    }
+
+    pub fn set_color(&self, color: &RGBColor) -> Result<(), Error> {
+        let color = *color;
+        self.call(move |c| c.set_color(&color))
+    }
+
+    /// Sets LED colors of all controllers, the frame for each controller is produced by the
+    /// closure
+    pub fn set_colors<F>(&self, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor> + Send + 'static,
+    {
+        self.call(move |c| c.set_colors(f))
+    }
+
+    pub fn set_zone_colors(
+        &self,
+        controller_id: u32,
+        zone_id: u32,
+        colors: &[RGBColor],
+    ) -> Result<(), Error> {
+        let colors = colors.to_vec();
+        self.call(move |c| c.set_zone_colors(controller_id, zone_id, &colors))
+    }
+
+    pub fn set_led_color(
+        &self,
+        controller_id: u32,
+        led_id: u32,
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        let color = *color;
+        self.call(move |c| c.set_led_color(controller_id, led_id, &color))
+    }
+
+    /// Sets color of the LEDs, matching any of the selectors, the rest of LEDs are left
+    /// untouched
+    pub fn set_leds_color(&self, selectors: &[LedSelector], color: &RGBColor) -> Result<(), Error> {
+        let (selectors, color) = (selectors.to_vec(), *color);
+        self.call(move |c| c.set_leds_color(&selectors, &color))
+    }
+
+    pub fn set_mode(&self, controller_id: u32, mode_id: u32) -> Result<(), Error> {
+        self.call(move |c| c.set_mode(controller_id, mode_id))
+    }
+
+    pub fn update_mode(
+        &self,
+        controller_id: u32,
+        mode_id: u32,
+        mode: &ModeData,
+    ) -> Result<(), Error> {
+        let mode = mode.clone();
+        self.call(move |c| c.update_mode(controller_id, mode_id, &mode))
+    }
+
+    pub fn save_mode(&self, controller_id: u32, mode_id: u32) -> Result<(), Error> {
+        self.call(move |c| c.save_mode(controller_id, mode_id))
+    }
+
+    pub fn set_custom_mode(&self, controller_id: u32) -> Result<(), Error> {
+        self.call(move |c| c.set_custom_mode(controller_id))
+    }
+
+    pub fn profiles(&self) -> Result<Vec<String>, Error> {
+        self.call(OpenRGBClient::profiles)
+    }
+
+    pub fn save_profile(&self, name: &str) -> Result<(), Error> {
+        let name = name.to_owned();
+        self.call(move |c| c.save_profile(&name))
+    }
+
+    pub fn load_profile(&self, name: &str) -> Result<(), Error> {
+        let name = name.to_owned();
+        self.call(move |c| c.load_profile(&name))
+    }
+
+    pub fn delete_profile(&self, name: &str) -> Result<(), Error> {
+        let name = name.to_owned();
+        self.call(move |c| c.delete_profile(&name))
+    }
+}
+
+fn worker_stopped() -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "client worker stopped",
+    ))
+}
@@ -1,28 +1,123 @@
 use colored::Colorize;
 use log::{debug, error};
-use std::convert::TryInto;
+use std::convert::TryFrom;
 use std::fmt;
 use std::io::{self, Read, Write};
-use std::net::TcpStream;
 use std::time::Duration;
 
-const PROTOCOL_VERSION: u32 = 2;
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod band;
+pub mod capture;
+pub mod color;
+pub mod gradient;
+pub mod handle;
+#[cfg(feature = "mock-server")]
+pub mod mock;
+pub mod monitor;
+pub mod selector;
+pub mod transport;
+
+use capture::{Capture, Direction};
+use selector::{select_leds, LedSelector, Selector};
+use transport::{Connector, Transport};
+
+/// The highest OpenRGB SDK protocol version supported by the client
+pub const PROTOCOL_VERSION: u32 = 4;
 
 const REQ_REQUEST_PROTOCOL_VERSION: u32 = 40;
 const REQ_SET_CLIENT_NAME: u32 = 50;
+const NET_PACKET_ID_DEVICE_LIST_UPDATED: u32 = 100;
+const REQ_REQUEST_PROFILE_LIST: u32 = 150;
+const REQ_REQUEST_SAVE_PROFILE: u32 = 151;
+const REQ_REQUEST_LOAD_PROFILE: u32 = 152;
+const REQ_REQUEST_DELETE_PROFILE: u32 = 153;
 const REQ_REQUEST_CONTROLLER_COUNT: u32 = 0;
 const REQ_REQUEST_CONTROLLER_DATA: u32 = 1;
 const REQ_RGBCONTROLLER_UPDATELEDS: u32 = 1050;
-//const REQ_RGBCONTROLLER_UPDATEMODE:u32 = 1101;
+const REQ_RGBCONTROLLER_UPDATEZONELEDS: u32 = 1051;
+const REQ_RGBCONTROLLER_UPDATESINGLELED: u32 = 1052;
+const REQ_RGBCONTROLLER_SETCUSTOMMODE: u32 = 1100;
+const REQ_RGBCONTROLLER_UPDATEMODE: u32 = 1101;
+const REQ_RGBCONTROLLER_SAVEMODE: u32 = 1102;
 
 const HEADER: [u8; 4] = [b'O', b'R', b'G', b'B'];
-
-const ERR_CONTROLLER_NOT_FOUND: &str = "controller not found";
+/// The maximum packet data size, larger packets are rejected as malformed before allocating the
+/// buffer
+pub const MAX_PACKET_SIZE: u32 = 16 * 1024 * 1024;
 
 const CLIENT_NAME: &str = "rgbmon";
 pub const VERSION: &str = "0.0.2";
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The server response does not follow the protocol (unexpected packet, wrong header etc.)
+    Protocol(String),
+    /// The packet data is truncated or invalid
+    Malformed {
+        offset: usize,
+        field: String,
+        reason: String,
+    },
+    /// The request requires a higher protocol version than negotiated with the server
+    UnsupportedVersion {
+        required: u32,
+        negotiated: u32,
+    },
+    /// No controllers match the selector
+    ControllerNotFound(String),
+    /// Zone, LED or mode not found
+    NotFound(String),
+    /// Unable to parse the value (device type, color etc.)
+    InvalidValue(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Error::Malformed {
+                offset,
+                field,
+                reason,
+            } => write!(
+                f,
+                "malformed packet at offset {}: {}, {}",
+                offset, field, reason
+            ),
+            Error::UnsupportedVersion {
+                required,
+                negotiated,
+            } => write!(
+                f,
+                "protocol {}+ required, negotiated with the server: {}",
+                required, negotiated
+            ),
+            Error::ControllerNotFound(selector) => write!(f, "controller not found: {}", selector),
+            Error::NotFound(what) => write!(f, "{} not found", what),
+            Error::InvalidValue(msg) => write!(f, "invalid value: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub struct RGBColor {
     pub red: u8,
     pub green: u8,
@@ -32,8 +127,8 @@ pub struct RGBColor {
 impl fmt::Display for RGBColor {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut color = String::new();
-        for c in vec![self.red, self.green, self.blue] {
-            let z = format!("{:#04X}", c as u32);
+        for c in &[self.red, self.green, self.blue] {
+            let z = format!("{:#04X}", *c as u32);
             color += &z.as_str()[2..];
         }
         write!(f, "{}", color)
@@ -60,67 +155,114 @@ impl RGBColor {
     pub fn colorize_self(&self) -> colored::ColoredString {
         self.colorize(self)
     }
+}
 
-    pub fn from_str(s: &str) -> Self {
-        Self {
-            red: u8::from_str_radix(&s[0..2], 16).unwrap(),
-            green: u8::from_str_radix(&s[2..4], 16).unwrap(),
-            blue: u8::from_str_radix(&s[4..6], 16).unwrap(),
-        }
-    }
-
-    pub fn rainbow(step: u32, total: u32, start: u32, end: u32) -> Self {
-        let coef: f32 = (total - start) as f32 / total as f32 - (total - end) as f32 / total as f32;
-        let sstep: f32 = (step as f32 * coef * total as f32 / 100.) + start as f32;
-        let r: f32;
-        let g: f32;
-        let b: f32;
-        let h: f32 = 1. - (sstep as f32 / total as f32);
-        let i: u32 = !!((h * 6.) as u32);
-        let f: f32 = h * 6. - i as f32;
-        let q: f32 = 1. - f;
-        match i % 6 {
-            0 => {
-                r = 1.;
-                g = f;
-                b = 0.;
-            }
-            1 => {
-                r = q;
-                g = 1.;
-                b = 0.;
-            }
-            2 => {
-                r = 0.;
-                g = 1.;
-                b = f;
-            }
-            3 => {
-                r = 0.;
-                g = q;
-                b = 1.;
-            }
-            4 => {
-                r = f;
-                g = 0.;
-                b = 1.;
-            }
-            5 => {
-                r = 1.;
-                g = 0.;
-                b = q;
-            }
-            _ => panic!(),
+/// OpenRGB device types. Unknown(_) keeps the raw value for types, not known to the client
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DeviceType {
+    Motherboard,
+    Dram,
+    Gpu,
+    Cooler,
+    LedStrip,
+    Keyboard,
+    Mouse,
+    MouseMat,
+    Headset,
+    HeadsetStand,
+    Gamepad,
+    Light,
+    Speaker,
+    Virtual,
+    Storage,
+    Case,
+    Microphone,
+    Accessory,
+    Keypad,
+    Laptop,
+    Monitor,
+    Unknown(u32),
+}
+
+const DEVICE_TYPE_UNKNOWN: u32 = 21;
+
+const DEVICE_TYPE_NAMES: [(DeviceType, &str); 21] = [
+    (DeviceType::Motherboard, "motherboard"),
+    (DeviceType::Dram, "dram"),
+    (DeviceType::Gpu, "gpu"),
+    (DeviceType::Cooler, "cooler"),
+    (DeviceType::LedStrip, "ledstrip"),
+    (DeviceType::Keyboard, "keyboard"),
+    (DeviceType::Mouse, "mouse"),
+    (DeviceType::MouseMat, "mousemat"),
+    (DeviceType::Headset, "headset"),
+    (DeviceType::HeadsetStand, "headsetstand"),
+    (DeviceType::Gamepad, "gamepad"),
+    (DeviceType::Light, "light"),
+    (DeviceType::Speaker, "speaker"),
+    (DeviceType::Virtual, "virtual"),
+    (DeviceType::Storage, "storage"),
+    (DeviceType::Case, "case"),
+    (DeviceType::Microphone, "microphone"),
+    (DeviceType::Accessory, "accessory"),
+    (DeviceType::Keypad, "keypad"),
+    (DeviceType::Laptop, "laptop"),
+    (DeviceType::Monitor, "monitor"),
+];
+
+impl From<u32> for DeviceType {
+    fn from(v: u32) -> Self {
+        match DEVICE_TYPE_NAMES.get(v as usize) {
+            Some((t, _)) => *t,
+            None => DeviceType::Unknown(v),
         }
-        Self {
-            red: !!((r * 235.) as u8),
-            green: !!((g * 235.) as u8),
-            blue: !!((b * 235.) as u8),
+    }
+}
+
+impl From<DeviceType> for u32 {
+    fn from(t: DeviceType) -> Self {
+        match t {
+            DeviceType::Unknown(v) => v,
+            _ => DEVICE_TYPE_NAMES.iter().position(|(d, _)| *d == t).unwrap() as u32,
         }
     }
 }
 
-#[derive(Debug)]
+impl fmt::Display for DeviceType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceType::Unknown(DEVICE_TYPE_UNKNOWN) => write!(f, "unknown"),
+            DeviceType::Unknown(v) => write!(f, "{}", v),
+            _ => write!(f, "{}", DEVICE_TYPE_NAMES[u32::from(*self) as usize].1),
+        }
+    }
+}
+
+impl std::str::FromStr for DeviceType {
+    type Err = Error;
+
+    /// Parses device type name (case-insensitive, "_" and "-" are ignored) or number
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(v) = s.parse::<u32>() {
+            return Ok(v.into());
+        }
+        let name: String = s
+            .chars()
+            .filter(|c| *c != '_' && *c != '-')
+            .collect::<String>()
+            .to_lowercase();
+        if name == "unknown" {
+            return Ok(DeviceType::Unknown(DEVICE_TYPE_UNKNOWN));
+        }
+        DEVICE_TYPE_NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(t, _)| *t)
+            .ok_or_else(|| Error::InvalidValue(format!("unknown device type: {}", s)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ControllerMetaData {
     pub vendor: String,
     pub description: String,
@@ -129,109 +271,492 @@ pub struct ControllerMetaData {
     pub location: String,
 }
 
-#[derive(Debug)]
+pub const MODE_FLAG_HAS_SPEED: u32 = 1 << 0;
+pub const MODE_FLAG_HAS_DIRECTION_LR: u32 = 1 << 1;
+pub const MODE_FLAG_HAS_DIRECTION_UD: u32 = 1 << 2;
+pub const MODE_FLAG_HAS_DIRECTION_HV: u32 = 1 << 3;
+pub const MODE_FLAG_HAS_BRIGHTNESS: u32 = 1 << 4;
+pub const MODE_FLAG_HAS_PER_LED_COLOR: u32 = 1 << 5;
+pub const MODE_FLAG_HAS_MODE_SPECIFIC_COLOR: u32 = 1 << 6;
+pub const MODE_FLAG_HAS_RANDOM_COLOR: u32 = 1 << 7;
+pub const MODE_FLAG_MANUAL_SAVE: u32 = 1 << 8;
+pub const MODE_FLAG_AUTOMATIC_SAVE: u32 = 1 << 9;
+
+pub const MODE_COLORS_NONE: u32 = 0;
+pub const MODE_COLORS_PER_LED: u32 = 1;
+pub const MODE_COLORS_MODE_SPECIFIC: u32 = 2;
+pub const MODE_COLORS_RANDOM: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeData {
+    pub name: String,
+    pub value: i32,
+    pub flags: u32,
+    pub speed_min: u32,
+    pub speed_max: u32,
+    pub brightness_min: u32,
+    pub brightness_max: u32,
+    pub colors_min: u32,
+    pub colors_max: u32,
+    pub speed: u32,
+    pub brightness: u32,
+    pub direction: u32,
+    pub color_mode: u32,
+    pub colors: Vec<RGBColor>,
+}
+
+impl ModeData {
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Direct (or Static) mode, which accepts per-LED colors from the SDK
+    pub fn is_direct(&self) -> bool {
+        self.color_mode == MODE_COLORS_PER_LED && self.has_flag(MODE_FLAG_HAS_PER_LED_COLOR)
+    }
+}
+
+pub const ZONE_TYPE_SINGLE: u32 = 0;
+pub const ZONE_TYPE_LINEAR: u32 = 1;
+pub const ZONE_TYPE_MATRIX: u32 = 2;
+
+/// Value used in matrix maps for the cells without a LED
+pub const MATRIX_NO_LED: u32 = 0xFFFF_FFFF;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixMap {
+    pub height: u32,
+    pub width: u32,
+    pub map: Vec<u32>,
+}
+
+impl MatrixMap {
+    /// Returns zone LED index at the given position, if any
+    pub fn get(&self, row: u32, col: u32) -> Option<u32> {
+        if row >= self.height || col >= self.width {
+            return None;
+        }
+        let index = (row as usize)
+            .checked_mul(self.width as usize)?
+            .checked_add(col as usize)?;
+        match self.map.get(index) {
+            Some(&v) if v != MATRIX_NO_LED => Some(v),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentData {
+    pub name: String,
+    pub segment_type: u32,
+    pub start_idx: u32,
+    pub leds_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneData {
+    pub name: String,
+    pub zone_type: u32,
+    pub leds_min: u32,
+    pub leds_max: u32,
+    pub leds_count: u32,
+    pub matrix_map: Option<MatrixMap>,
+    pub segments: Vec<SegmentData>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LedData {
     pub name: String,
     pub value: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ControllerData {
     pub id: u32,
     pub name: String,
     pub metadata: ControllerMetaData,
-    pub device_type: u32,
+    pub device_type: DeviceType,
+    pub modes: Vec<ModeData>,
+    pub active_mode: i32,
+    pub zones: Vec<ZoneData>,
     pub leds: Vec<LedData>,
+    pub colors: Vec<RGBColor>,
 }
 
-macro_rules! unwrap_data {
-    ( $e:expr ) => {
-        match $e {
-            Ok(x) => x,
-            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+macro_rules! check_batch {
+    ( $e:expr, $($selector:tt)+ ) => {
+        if $e.is_empty() {
+            return Err(Error::ControllerNotFound(format!($($selector)+)));
         }
     };
 }
 
-macro_rules! try_data {
-    ( $e:expr ) => {
-        match $e.try_into() {
-            Ok(x) => x,
-            Err(_) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Invalid data received",
-                ))
+/// Bounds-checked cursor over the packet data. All reads return InvalidData errors with the
+/// offset and the field name when the packet is truncated or malformed
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn malformed(&self, field: &str, reason: &str) -> Error {
+        Error::Malformed {
+            offset: self.pos,
+            field: field.to_owned(),
+            reason: reason.to_owned(),
+        }
+    }
+
+    fn take(&mut self, len: usize, field: &str) -> Result<&'a [u8], Error> {
+        match self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+        {
+            Some(v) => {
+                self.pos += len;
+                Ok(v)
             }
+            None => Err(self.malformed(
+                field,
+                &format!(
+                    "{} byte(s) required, {} available",
+                    len,
+                    self.data.len().saturating_sub(self.pos)
+                ),
+            )),
         }
-    };
+    }
+
+    fn read_u16(&mut self, field: &str) -> Result<u16, Error> {
+        let v = self.take(2, field)?;
+        Ok(u16::from_le_bytes([v[0], v[1]]))
+    }
+
+    fn read_u32(&mut self, field: &str) -> Result<u32, Error> {
+        let v = self.take(4, field)?;
+        Ok(u32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+    }
+
+    fn read_i32(&mut self, field: &str) -> Result<i32, Error> {
+        Ok(self.read_u32(field)? as i32)
+    }
+
+    fn read_string(&mut self, field: &str) -> Result<String, Error> {
+        let len = self.read_u16(field)? as usize;
+        let pos = self.pos;
+        let v = self.take(len, field)?;
+        // the length includes the null terminator
+        let v = match v.split_last() {
+            Some((0, s)) => s,
+            Some(_) => v,
+            None => &[],
+        };
+        String::from_utf8(v.to_vec()).map_err(|_| {
+            self.pos = pos;
+            self.malformed(field, "invalid UTF-8 string")
+        })
+    }
+
+    /// Reads null-terminated string, which takes the rest of the data
+    fn read_cstring(&mut self, field: &str) -> Result<String, Error> {
+        let pos = self.pos;
+        let v = self.take(self.data.len() - self.pos, field)?;
+        let v = match v.iter().position(|c| *c == 0) {
+            Some(end) => &v[..end],
+            None => v,
+        };
+        String::from_utf8(v.to_vec()).map_err(|_| {
+            self.pos = pos;
+            self.malformed(field, "invalid UTF-8 string")
+        })
+    }
+
+    fn read_color(&mut self, field: &str) -> Result<RGBColor, Error> {
+        let v = self.take(4, field)?;
+        Ok(RGBColor::new(v[0], v[1], v[2]))
+    }
+
+    fn read_colors(&mut self, field: &str) -> Result<Vec<RGBColor>, Error> {
+        let num_colors = self.read_u16(field)?;
+        let mut colors = Vec::with_capacity(num_colors as usize);
+        for _ in 0..num_colors {
+            colors.push(self.read_color(field)?);
+        }
+        Ok(colors)
+    }
 }
 
-macro_rules! check_batch {
-    ( $e:expr ) => {
-        if $e.is_empty() {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                ERR_CONTROLLER_NOT_FOUND,
-            ));
+fn pack_color(buf: &mut Vec<u8>, color: &RGBColor) {
+    buf.push(color.red);
+    buf.push(color.green);
+    buf.push(color.blue);
+    buf.push(0x00); // X
+}
+
+/// Packs a 16-bit length or count field, larger values do not fit into the packet
+fn pack_len(buf: &mut Vec<u8>, len: usize, field: &str) -> Result<(), Error> {
+    let len = u16::try_from(len).map_err(|_| {
+        Error::InvalidValue(format!("{}: {} does not fit into 16 bits", field, len))
+    })?;
+    buf.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+fn pack_string(buf: &mut Vec<u8>, s: &str, field: &str) -> Result<(), Error> {
+    pack_len(buf, s.len() + 1, field)?;
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+    Ok(())
+}
+
+fn pack_colors(buf: &mut Vec<u8>, colors: &[RGBColor], field: &str) -> Result<(), Error> {
+    pack_len(buf, colors.len(), field)?;
+    for color in colors {
+        pack_color(buf, color);
+    }
+    Ok(())
+}
+
+/// Prepends the data with its size, including the size field itself
+fn with_data_size(data: Vec<u8>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(data.len() + 4);
+    buf.extend_from_slice(&(data.len() as u32 + 4).to_le_bytes());
+    buf.extend_from_slice(&data);
+    buf
+}
+
+impl SegmentData {
+    fn pack(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        pack_string(buf, &self.name, "segment name")?;
+        buf.extend_from_slice(&self.segment_type.to_le_bytes());
+        buf.extend_from_slice(&self.start_idx.to_le_bytes());
+        buf.extend_from_slice(&self.leds_count.to_le_bytes());
+        Ok(())
+    }
+
+    fn unpack(r: &mut Reader) -> Result<Self, Error> {
+        Ok(Self {
+            name: r.read_string("segment name")?,
+            segment_type: r.read_u32("segment type")?,
+            start_idx: r.read_u32("segment start index")?,
+            leds_count: r.read_u32("segment LEDs count")?,
+        })
+    }
+}
+
+impl ZoneData {
+    fn pack(&self, buf: &mut Vec<u8>, protocol: u32) -> Result<(), Error> {
+        pack_string(buf, &self.name, "zone name")?;
+        buf.extend_from_slice(&self.zone_type.to_le_bytes());
+        buf.extend_from_slice(&self.leds_min.to_le_bytes());
+        buf.extend_from_slice(&self.leds_max.to_le_bytes());
+        buf.extend_from_slice(&self.leds_count.to_le_bytes());
+        match self.matrix_map {
+            Some(ref m) => {
+                pack_len(buf, m.map.len() * 4 + 8, "zone matrix length")?;
+                buf.extend_from_slice(&m.height.to_le_bytes());
+                buf.extend_from_slice(&m.width.to_le_bytes());
+                for v in &m.map {
+                    buf.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+            None => buf.extend_from_slice(&0u16.to_le_bytes()),
         }
-    };
+        if protocol >= 4 {
+            pack_len(buf, self.segments.len(), "zone segments count")?;
+            for segment in &self.segments {
+                segment.pack(buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn unpack(r: &mut Reader, protocol: u32) -> Result<Self, Error> {
+        let name = r.read_string("zone name")?;
+        let zone_type = r.read_u32("zone type")?;
+        let leds_min = r.read_u32("zone LEDs min")?;
+        let leds_max = r.read_u32("zone LEDs max")?;
+        let leds_count = r.read_u32("zone LEDs count")?;
+        let matrix_len = r.read_u16("zone matrix length")?;
+        let matrix_map = if matrix_len > 0 {
+            let height = r.read_u32("zone matrix height")?;
+            let width = r.read_u32("zone matrix width")?;
+            // the dimensions are not trusted until they match the length
+            let size = (height as usize)
+                .checked_mul(width as usize)
+                .filter(|size| {
+                    size.checked_mul(4).and_then(|len| len.checked_add(8))
+                        == Some(matrix_len as usize)
+                })
+                .ok_or_else(|| r.malformed("zone matrix map", "size mismatch"))?;
+            let mut map = Vec::with_capacity(size);
+            for _ in 0..size {
+                map.push(r.read_u32("zone matrix map")?);
+            }
+            Some(MatrixMap { height, width, map })
+        } else {
+            None
+        };
+        let mut segments = Vec::new();
+        if protocol >= 4 {
+            let num_segments = r.read_u16("zone segments count")?;
+            for _ in 0..num_segments {
+                segments.push(SegmentData::unpack(r)?);
+            }
+        }
+        Ok(Self {
+            name,
+            zone_type,
+            leds_min,
+            leds_max,
+            leds_count,
+            matrix_map,
+            segments,
+        })
+    }
 }
 
-fn parse_string(pos: usize, data: &[u8]) -> Result<(usize, String), io::Error> {
-    let string_len = u16::from_le_bytes(try_data!(data[pos..pos + 2])) as usize;
-    let result = unwrap_data!(String::from_utf8(try_data!(
-        data[pos + 2..pos + 1 + string_len]
-    )));
-    Ok((pos + string_len + 2, result))
+impl ModeData {
+    /// Packs the mode description, as it is sent in controller data and mode requests
+    pub fn pack(&self, protocol: u32) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        pack_string(&mut buf, &self.name, "mode name")?;
+        buf.extend_from_slice(&self.value.to_le_bytes());
+        buf.extend_from_slice(&self.flags.to_le_bytes());
+        buf.extend_from_slice(&self.speed_min.to_le_bytes());
+        buf.extend_from_slice(&self.speed_max.to_le_bytes());
+        if protocol >= 3 {
+            buf.extend_from_slice(&self.brightness_min.to_le_bytes());
+            buf.extend_from_slice(&self.brightness_max.to_le_bytes());
+        }
+        buf.extend_from_slice(&self.colors_min.to_le_bytes());
+        buf.extend_from_slice(&self.colors_max.to_le_bytes());
+        buf.extend_from_slice(&self.speed.to_le_bytes());
+        if protocol >= 3 {
+            buf.extend_from_slice(&self.brightness.to_le_bytes());
+        }
+        buf.extend_from_slice(&self.direction.to_le_bytes());
+        buf.extend_from_slice(&self.color_mode.to_le_bytes());
+        pack_colors(&mut buf, &self.colors, "mode colors")?;
+        Ok(buf)
+    }
+
+    fn unpack(r: &mut Reader, protocol: u32) -> Result<Self, Error> {
+        let name = r.read_string("mode name")?;
+        let value = r.read_i32("mode value")?;
+        let flags = r.read_u32("mode flags")?;
+        let speed_min = r.read_u32("mode speed min")?;
+        let speed_max = r.read_u32("mode speed max")?;
+        let (brightness_min, brightness_max) = if protocol >= 3 {
+            (
+                r.read_u32("mode brightness min")?,
+                r.read_u32("mode brightness max")?,
+            )
+        } else {
+            (0, 0)
+        };
+        let colors_min = r.read_u32("mode colors min")?;
+        let colors_max = r.read_u32("mode colors max")?;
+        let speed = r.read_u32("mode speed")?;
+        let brightness = if protocol >= 3 {
+            r.read_u32("mode brightness")?
+        } else {
+            0
+        };
+        let direction = r.read_u32("mode direction")?;
+        let color_mode = r.read_u32("mode color mode")?;
+        let colors = r.read_colors("mode colors")?;
+        Ok(Self {
+            name,
+            value,
+            flags,
+            speed_min,
+            speed_max,
+            brightness_min,
+            brightness_max,
+            colors_min,
+            colors_max,
+            speed,
+            brightness,
+            direction,
+            color_mode,
+            colors,
+        })
+    }
 }
 
 impl ControllerData {
-    fn unpack(id: u32, data: &[u8]) -> Result<Self, io::Error> {
-        let device_type = u32::from_le_bytes(try_data!(data[4..8]));
-        let (pos, name) = parse_string(8, data)?;
-        let (pos, vendor) = parse_string(pos, data)?;
-        let (pos, description) = parse_string(pos, data)?;
-        let (pos, version) = parse_string(pos, data)?;
-        let (pos, serial) = parse_string(pos, data)?;
-        let (mut pos, location) = parse_string(pos, data)?;
-        let num_modes = u16::from_le_bytes(try_data!(data[pos..pos + 2]));
-        let _active_mode = i32::from_le_bytes(try_data!(data[pos + 2..pos + 6]));
-        pos += 6;
+    /// Packs REQUEST_CONTROLLER_DATA server response. The id is not a part of the data
+    pub fn pack(&self, protocol: u32) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&u32::from(self.device_type).to_le_bytes());
+        pack_string(&mut buf, &self.name, "name")?;
+        if protocol >= 1 {
+            pack_string(&mut buf, &self.metadata.vendor, "vendor")?;
+        }
+        pack_string(&mut buf, &self.metadata.description, "description")?;
+        pack_string(&mut buf, &self.metadata.version, "version")?;
+        pack_string(&mut buf, &self.metadata.serial, "serial")?;
+        pack_string(&mut buf, &self.metadata.location, "location")?;
+        pack_len(&mut buf, self.modes.len(), "modes count")?;
+        buf.extend_from_slice(&self.active_mode.to_le_bytes());
+        for mode in &self.modes {
+            buf.extend_from_slice(&mode.pack(protocol)?);
+        }
+        pack_len(&mut buf, self.zones.len(), "zones count")?;
+        for zone in &self.zones {
+            zone.pack(&mut buf, protocol)?;
+        }
+        pack_len(&mut buf, self.leds.len(), "LEDs count")?;
+        for led in &self.leds {
+            pack_string(&mut buf, &led.name, "LED name")?;
+            buf.extend_from_slice(&led.value.to_le_bytes());
+        }
+        pack_colors(&mut buf, &self.colors, "colors")?;
+        Ok(with_data_size(buf))
+    }
+
+    /// Parses REQUEST_CONTROLLER_DATA server response
+    pub fn unpack(id: u32, protocol: u32, data: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(data);
+        r.read_u32("data size")?;
+        let device_type = r.read_u32("device type")?.into();
+        let name = r.read_string("name")?;
+        let vendor = if protocol >= 1 {
+            r.read_string("vendor")?
+        } else {
+            String::new()
+        };
+        let description = r.read_string("description")?;
+        let version = r.read_string("version")?;
+        let serial = r.read_string("serial")?;
+        let location = r.read_string("location")?;
+        let num_modes = r.read_u16("modes count")?;
+        let active_mode = r.read_i32("active mode")?;
+        let mut modes: Vec<ModeData> = Vec::with_capacity(num_modes as usize);
         for _ in 0..num_modes {
-            let (p, _mode_name) = parse_string(pos, data)?;
-            pos = p;
-            let num_colors = u16::from_le_bytes(try_data!(data[pos + 36..pos + 38]));
-            pos = pos + 38 + num_colors as usize * 4;
-            if device_type == 1 {
-                //println!("{}", num_colors);
-            }
+            modes.push(ModeData::unpack(&mut r, protocol)?);
         }
-        let num_zones = u16::from_le_bytes(try_data!(data[pos..pos + 2]));
-        pos += 2;
+        let num_zones = r.read_u16("zones count")?;
+        let mut zones: Vec<ZoneData> = Vec::with_capacity(num_zones as usize);
         for _ in 0..num_zones {
-            let (p, _zone_name) = parse_string(pos, data)?;
-            pos = p + 18;
-            if data[pos] == 2 {
-                // ZoneType Matrix, untested
-                let height = u32::from_le_bytes(try_data!(data[pos..pos + 4]));
-                let width = u32::from_le_bytes(try_data!(data[pos + 4..pos + 8]));
-                pos += height as usize * width as usize * 4;
-            }
-        }
-        let num_leds = u16::from_le_bytes(try_data!(data[pos..pos + 2]));
-        pos += 2;
-        let mut leds: Vec<LedData> = Vec::new();
+            zones.push(ZoneData::unpack(&mut r, protocol)?);
+        }
+        let num_leds = r.read_u16("LEDs count")?;
+        let mut leds: Vec<LedData> = Vec::with_capacity(num_leds as usize);
         for _ in 0..num_leds {
-            let (p, led_name) = parse_string(pos, data)?;
-            pos = p;
-            let value = u32::from_le_bytes(try_data!(data[pos..pos + 4]));
-            pos += 4;
             leds.push(LedData {
-                name: led_name,
-                value,
+                name: r.read_string("LED name")?,
+                value: r.read_u32("LED value")?,
             })
         }
+        let colors = r.read_colors("colors")?;
         Ok(Self {
             id,
             name,
@@ -243,58 +768,745 @@ impl ControllerData {
                 serial,
                 location,
             },
+            modes,
+            active_mode,
+            zones,
             leds,
+            colors,
         })
     }
+
+    /// Returns the current color of the LED
+    pub fn led_color(&self, led_id: usize) -> Option<RGBColor> {
+        self.colors.get(led_id).copied()
+    }
+
+    /// Updates the LED colors with the LED update request, so they match the colors on the server
+    pub(crate) fn apply_colors(&mut self, request: &Request) {
+        match request {
+            Request::UpdateLeds(colors) => {
+                for (dst, src) in self.colors.iter_mut().zip(colors) {
+                    *dst = *src;
+                }
+            }
+            Request::UpdateZoneLeds { zone_id, colors } => {
+                if let Some(range) = self.zone_leds(*zone_id as usize) {
+                    for (i, color) in range.zip(colors) {
+                        if let Some(v) = self.colors.get_mut(i) {
+                            *v = *color;
+                        }
+                    }
+                }
+            }
+            Request::UpdateSingleLed { led_id, color } => {
+                if let Some(v) = self.colors.get_mut(*led_id as usize) {
+                    *v = *color;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the range of controller LED indexes, which belong to the zone
+    pub fn zone_leds(&self, zone_id: usize) -> Option<std::ops::Range<usize>> {
+        let zone = self.zones.get(zone_id)?;
+        let start: usize = self.zones[..zone_id]
+            .iter()
+            .map(|z| z.leds_count as usize)
+            .sum();
+        Some(start..start + zone.leds_count as usize)
+    }
+
+    pub fn active_mode(&self) -> Option<&ModeData> {
+        if self.active_mode < 0 {
+            None
+        } else {
+            self.modes.get(self.active_mode as usize)
+        }
+    }
+
+    /// Returns the mode the server switches the controller to on SetCustomMode: Direct, Custom or
+    /// Static, whichever exists first
+    pub fn custom_mode(&self) -> Option<usize> {
+        for name in &["Direct", "Custom", "Static"] {
+            if let Some(i) = self.modes.iter().position(|m| m.name == *name) {
+                return Some(i);
+            }
+        }
+        None
+    }
+}
+
+/// OpenRGB SDK packet header
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PacketHeader {
+    pub device_id: u32,
+    pub packet_type: u32,
+    pub len: u32,
+}
+
+impl PacketHeader {
+    pub const SIZE: usize = 16;
+
+    pub fn pack(&self) -> [u8; PacketHeader::SIZE] {
+        let mut buf = [0u8; PacketHeader::SIZE];
+        buf[..4].copy_from_slice(&HEADER);
+        buf[4..8].copy_from_slice(&self.device_id.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.packet_type.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.len.to_le_bytes());
+        buf
+    }
+
+    pub fn unpack(data: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(data);
+        if r.take(4, "header")? != HEADER {
+            return Err(Error::Protocol("invalid packet header".to_owned()));
+        }
+        let device_id = r.read_u32("device id")?;
+        let packet_type = r.read_u32("packet type")?;
+        let len = r.read_u32("packet length")?;
+        if len > MAX_PACKET_SIZE {
+            return Err(Error::Malformed {
+                offset: 12,
+                field: "packet length".to_owned(),
+                reason: format!("{} bytes, the maximum is {}", len, MAX_PACKET_SIZE),
+            });
+        }
+        Ok(Self {
+            device_id,
+            packet_type,
+            len,
+        })
+    }
+}
+
+/// Builds a complete packet: the header and the data
+pub fn encode_packet(device_id: u32, packet_type: u32, data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(PacketHeader::SIZE + data.len());
+    buf.extend_from_slice(
+        &PacketHeader {
+            device_id,
+            packet_type,
+            len: data.len() as u32,
+        }
+        .pack(),
+    );
+    buf.extend_from_slice(data);
+    buf
+}
+
+/// Packets, sent by the client
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    RequestControllerCount,
+    RequestControllerData { protocol: u32 },
+    RequestProtocolVersion(u32),
+    SetClientName(String),
+    RequestProfileList,
+    SaveProfile(String),
+    LoadProfile(String),
+    DeleteProfile(String),
+    UpdateLeds(Vec<RGBColor>),
+    UpdateZoneLeds { zone_id: u32, colors: Vec<RGBColor> },
+    UpdateSingleLed { led_id: u32, color: RGBColor },
+    SetCustomMode,
+    UpdateMode { mode_id: u32, mode: ModeData },
+    SaveMode { mode_id: u32, mode: ModeData },
+}
+
+impl Request {
+    pub fn packet_type(&self) -> u32 {
+        match self {
+            Request::RequestControllerCount => REQ_REQUEST_CONTROLLER_COUNT,
+            Request::RequestControllerData { .. } => REQ_REQUEST_CONTROLLER_DATA,
+            Request::RequestProtocolVersion(_) => REQ_REQUEST_PROTOCOL_VERSION,
+            Request::SetClientName(_) => REQ_SET_CLIENT_NAME,
+            Request::RequestProfileList => REQ_REQUEST_PROFILE_LIST,
+            Request::SaveProfile(_) => REQ_REQUEST_SAVE_PROFILE,
+            Request::LoadProfile(_) => REQ_REQUEST_LOAD_PROFILE,
+            Request::DeleteProfile(_) => REQ_REQUEST_DELETE_PROFILE,
+            Request::UpdateLeds(_) => REQ_RGBCONTROLLER_UPDATELEDS,
+            Request::UpdateZoneLeds { .. } => REQ_RGBCONTROLLER_UPDATEZONELEDS,
+            Request::UpdateSingleLed { .. } => REQ_RGBCONTROLLER_UPDATESINGLELED,
+            Request::SetCustomMode => REQ_RGBCONTROLLER_SETCUSTOMMODE,
+            Request::UpdateMode { .. } => REQ_RGBCONTROLLER_UPDATEMODE,
+            Request::SaveMode { .. } => REQ_RGBCONTROLLER_SAVEMODE,
+        }
+    }
+
+    /// Returns true if the server replies to the request
+    pub fn has_response(packet_type: u32) -> bool {
+        matches!(
+            packet_type,
+            REQ_REQUEST_CONTROLLER_COUNT
+                | REQ_REQUEST_CONTROLLER_DATA
+                | REQ_REQUEST_PROTOCOL_VERSION
+                | REQ_REQUEST_PROFILE_LIST
+        )
+    }
+
+    /// Packs the request data (without the header)
+    pub fn pack(&self, protocol: u32) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        match self {
+            Request::RequestControllerCount
+            | Request::RequestProfileList
+            | Request::SetCustomMode => {}
+            Request::RequestControllerData { protocol } => {
+                if *protocol > 0 {
+                    buf.extend_from_slice(&protocol.to_le_bytes());
+                }
+            }
+            Request::RequestProtocolVersion(v) => buf.extend_from_slice(&v.to_le_bytes()),
+            Request::SetClientName(name)
+            | Request::SaveProfile(name)
+            | Request::LoadProfile(name)
+            | Request::DeleteProfile(name) => {
+                buf.extend_from_slice(name.as_bytes());
+                buf.push(0);
+            }
+            Request::UpdateLeds(colors) => {
+                pack_colors(&mut buf, colors, "colors")?;
+                buf = with_data_size(buf);
+            }
+            Request::UpdateZoneLeds { zone_id, colors } => {
+                buf.extend_from_slice(&zone_id.to_le_bytes());
+                pack_colors(&mut buf, colors, "colors")?;
+                buf = with_data_size(buf);
+            }
+            Request::UpdateSingleLed { led_id, color } => {
+                buf.extend_from_slice(&led_id.to_le_bytes());
+                pack_color(&mut buf, color);
+            }
+            Request::UpdateMode { mode_id, mode } | Request::SaveMode { mode_id, mode } => {
+                buf.extend_from_slice(&mode_id.to_le_bytes());
+                buf.extend_from_slice(&mode.pack(protocol)?);
+                buf = with_data_size(buf);
+            }
+        }
+        Ok(buf)
+    }
+
+    pub fn unpack(packet_type: u32, protocol: u32, data: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(data);
+        Ok(match packet_type {
+            REQ_REQUEST_CONTROLLER_COUNT => Request::RequestControllerCount,
+            REQ_REQUEST_CONTROLLER_DATA => Request::RequestControllerData {
+                // protocol 0 clients send no data
+                protocol: if data.is_empty() {
+                    0
+                } else {
+                    r.read_u32("protocol version")?
+                },
+            },
+            REQ_REQUEST_PROTOCOL_VERSION => {
+                Request::RequestProtocolVersion(r.read_u32("protocol version")?)
+            }
+            REQ_SET_CLIENT_NAME => Request::SetClientName(r.read_cstring("client name")?),
+            REQ_REQUEST_PROFILE_LIST => Request::RequestProfileList,
+            REQ_REQUEST_SAVE_PROFILE => Request::SaveProfile(r.read_cstring("profile name")?),
+            REQ_REQUEST_LOAD_PROFILE => Request::LoadProfile(r.read_cstring("profile name")?),
+            REQ_REQUEST_DELETE_PROFILE => Request::DeleteProfile(r.read_cstring("profile name")?),
+            REQ_RGBCONTROLLER_UPDATELEDS => {
+                r.read_u32("data size")?;
+                Request::UpdateLeds(r.read_colors("colors")?)
+            }
+            REQ_RGBCONTROLLER_UPDATEZONELEDS => {
+                r.read_u32("data size")?;
+                Request::UpdateZoneLeds {
+                    zone_id: r.read_u32("zone id")?,
+                    colors: r.read_colors("colors")?,
+                }
+            }
+            REQ_RGBCONTROLLER_UPDATESINGLELED => Request::UpdateSingleLed {
+                led_id: r.read_u32("LED id")?,
+                color: r.read_color("color")?,
+            },
+            REQ_RGBCONTROLLER_SETCUSTOMMODE => Request::SetCustomMode,
+            REQ_RGBCONTROLLER_UPDATEMODE | REQ_RGBCONTROLLER_SAVEMODE => {
+                r.read_u32("data size")?;
+                let mode_id = r.read_u32("mode id")?;
+                let mode = ModeData::unpack(&mut r, protocol)?;
+                if packet_type == REQ_RGBCONTROLLER_UPDATEMODE {
+                    Request::UpdateMode { mode_id, mode }
+                } else {
+                    Request::SaveMode { mode_id, mode }
+                }
+            }
+            _ => {
+                return Err(Error::Protocol(format!(
+                    "unknown request packet {}",
+                    packet_type
+                )))
+            }
+        })
+    }
+
+    /// Builds the complete request packet
+    pub fn encode(&self, device_id: u32, protocol: u32) -> Result<Vec<u8>, Error> {
+        Ok(encode_packet(
+            device_id,
+            self.packet_type(),
+            &self.pack(protocol)?,
+        ))
+    }
+}
+
+/// Packets, sent by the server
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    ControllerCount(u32),
+    ControllerData(Box<ControllerData>),
+    ProtocolVersion(u32),
+    ProfileList(Vec<String>),
+    DeviceListUpdated,
+}
+
+impl Response {
+    pub fn packet_type(&self) -> u32 {
+        match self {
+            Response::ControllerCount(_) => REQ_REQUEST_CONTROLLER_COUNT,
+            Response::ControllerData(_) => REQ_REQUEST_CONTROLLER_DATA,
+            Response::ProtocolVersion(_) => REQ_REQUEST_PROTOCOL_VERSION,
+            Response::ProfileList(_) => REQ_REQUEST_PROFILE_LIST,
+            Response::DeviceListUpdated => NET_PACKET_ID_DEVICE_LIST_UPDATED,
+        }
+    }
+
+    /// Packs the response data (without the header)
+    pub fn pack(&self, protocol: u32) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        match self {
+            Response::ControllerCount(v) | Response::ProtocolVersion(v) => {
+                buf.extend_from_slice(&v.to_le_bytes())
+            }
+            Response::ControllerData(c) => buf = c.pack(protocol)?,
+            Response::ProfileList(profiles) => {
+                pack_len(&mut buf, profiles.len(), "profiles count")?;
+                for profile in profiles {
+                    pack_string(&mut buf, profile, "profile name")?;
+                }
+                buf = with_data_size(buf);
+            }
+            Response::DeviceListUpdated => {}
+        }
+        Ok(buf)
+    }
+
+    /// Parses the response data. For controller data, the device id is used as the controller id
+    pub fn unpack(
+        device_id: u32,
+        packet_type: u32,
+        protocol: u32,
+        data: &[u8],
+    ) -> Result<Self, Error> {
+        let mut r = Reader::new(data);
+        Ok(match packet_type {
+            REQ_REQUEST_CONTROLLER_COUNT => {
+                Response::ControllerCount(r.read_u32("controller count")?)
+            }
+            REQ_REQUEST_CONTROLLER_DATA => Response::ControllerData(Box::new(
+                ControllerData::unpack(device_id, protocol, data)?,
+            )),
+            REQ_REQUEST_PROTOCOL_VERSION => {
+                Response::ProtocolVersion(r.read_u32("protocol version")?)
+            }
+            REQ_REQUEST_PROFILE_LIST => {
+                r.read_u32("data size")?;
+                let num_profiles = r.read_u16("profiles count")?;
+                let mut profiles = Vec::with_capacity(num_profiles as usize);
+                for _ in 0..num_profiles {
+                    profiles.push(r.read_string("profile name")?);
+                }
+                Response::ProfileList(profiles)
+            }
+            NET_PACKET_ID_DEVICE_LIST_UPDATED => Response::DeviceListUpdated,
+            _ => {
+                return Err(Error::Protocol(format!(
+                    "unknown response packet {}",
+                    packet_type
+                )))
+            }
+        })
+    }
+
+    /// Builds the complete response packet
+    pub fn encode(&self, device_id: u32, protocol: u32) -> Result<Vec<u8>, Error> {
+        Ok(encode_packet(
+            device_id,
+            self.packet_type(),
+            &self.pack(protocol)?,
+        ))
+    }
+}
+
+/// Unsolicited packets, sent by the server
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Notification {
+    /// Devices have been added or removed, the controllers must be reloaded
+    DeviceListUpdated,
 }
 
 pub struct OpenRGBClient {
-    stream: Option<TcpStream>,
-    path: String,
+    stream: Option<Connection>,
+    capture: Option<Capture>,
+    notifications: Vec<Notification>,
+    connector: Box<dyn Connector>,
     pub retries: u8,
     pub timeout: Duration,
     pub controllers: Vec<ControllerData>,
     pub server_protocol: Option<u32>,
+    pub protocol: Option<u32>,
 }
 
 struct ControllerLedSetCommand {
     controller_id: u32,
-    end: u16,
+    colors: Vec<RGBColor>,
+}
+
+impl ControllerLedSetCommand {
+    fn new(controller: &ControllerData, mut colors: Vec<RGBColor>) -> Self {
+        colors.truncate(controller.leds.len());
+        Self {
+            controller_id: controller.id,
+            colors,
+        }
+    }
+}
+
+/// Ids of the controllers, where only some of LEDs are selected. Their current colors must be read
+/// before building the frames: other clients and profile loads may change the rest of LEDs
+pub(crate) fn partially_selected(
+    selectors: &[LedSelector],
+    controllers: &[ControllerData],
+) -> Vec<u32> {
+    select_leds(selectors, controllers)
+        .into_iter()
+        .filter(|(id, leds)| {
+            controllers
+                .iter()
+                .any(|c| c.id == *id && leds.len() < c.leds.len())
+        })
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Updates the controller colors with the ones, read from the server. If the LEDs do not match
+/// (the device list is changed, but not reloaded yet), the colors are reset to unknown
+pub(crate) fn update_colors(
+    controller: &mut ControllerData,
+    response: Response,
+) -> Result<(), Error> {
+    match response {
+        Response::ControllerData(c) => {
+            controller.colors = if c.leds == controller.leds {
+                c.colors
+            } else {
+                Vec::new()
+            };
+            Ok(())
+        }
+        r => Err(unexpected(r)),
+    }
+}
+
+/// Requests to set color of the selected LEDs: a single frame for each controller, the rest of
+/// LEDs keep their current colors. If the current colors are unknown, the LEDs are set one by one
+pub(crate) fn led_requests(
+    selectors: &[LedSelector],
+    controllers: &[ControllerData],
+    color: &RGBColor,
+) -> Result<Vec<(u32, Request)>, Error> {
+    let mut requests = Vec::new();
+    for (controller_id, leds) in select_leds(selectors, controllers) {
+        let controller = match controllers.iter().find(|c| c.id == controller_id) {
+            Some(v) => v,
+            None => continue,
+        };
+        let num_leds = controller.leds.len();
+        if leds.len() == num_leds {
+            requests.push((controller_id, Request::UpdateLeds(vec![*color; num_leds])));
+        } else if controller.colors.len() == num_leds {
+            let mut colors = controller.colors.clone();
+            for led_id in leds {
+                colors[led_id as usize] = *color;
+            }
+            requests.push((controller_id, Request::UpdateLeds(colors)));
+        } else {
+            for led_id in leds {
+                requests.push((
+                    controller_id,
+                    Request::UpdateSingleLed {
+                        led_id,
+                        color: *color,
+                    },
+                ));
+            }
+        }
+    }
+    if requests.is_empty() {
+        return Err(Error::NotFound(format!(
+            "LEDs {}",
+            selectors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(";")
+        )));
+    }
+    Ok(requests)
+}
+
+pub(crate) fn no_response() -> Error {
+    Error::Protocol("no response received".to_owned())
+}
+
+pub(crate) fn unexpected(response: Response) -> Error {
+    Error::Protocol(format!("unexpected response: {:?}", response))
+}
+
+/// Decodes the response data of the request
+pub(crate) fn decode_response(
+    device_id: u32,
+    request: &Request,
+    protocol: u32,
+    data: Option<Vec<u8>>,
+) -> Result<Option<Response>, Error> {
+    match data {
+        Some(data) => Ok(Some(Response::unpack(
+            device_id,
+            request.packet_type(),
+            protocol,
+            &data,
+        )?)),
+        None => Ok(None),
+    }
+}
+
+// The load sequence steps, shared by the sync and async clients: version negotiation, the client
+// name, the controller count and the controllers data
+
+pub(crate) fn version_request() -> Request {
+    Request::RequestProtocolVersion(PROTOCOL_VERSION)
+}
+
+/// Returns the server protocol version and the protocol to use. Servers with protocol 0 do not
+/// reply to the version request, so timeouts mean protocol 0. The connection must be reopened
+/// after errors to drop a late reply
+pub(crate) fn negotiate_protocol(result: Result<Response, Error>) -> Result<(u32, u32), Error> {
+    let server_protocol = match result {
+        Ok(Response::ProtocolVersion(v)) => v,
+        Ok(r) => return Err(unexpected(r)),
+        Err(Error::Io(e))
+            if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            0
+        }
+        Err(e) => return Err(e),
+    };
+    let protocol = std::cmp::min(PROTOCOL_VERSION, server_protocol);
+    debug!("server protocol: {}, using: {}", server_protocol, protocol);
+    Ok((server_protocol, protocol))
+}
+
+pub(crate) fn client_name_request() -> Request {
+    Request::SetClientName(format!("{} {}", CLIENT_NAME, VERSION))
+}
+
+pub(crate) fn controller_count(response: Response) -> Result<u32, Error> {
+    match response {
+        Response::ControllerCount(v) => {
+            debug!("{} controller(s) found", v);
+            Ok(v)
+        }
+        r => Err(unexpected(r)),
+    }
+}
+
+pub(crate) fn controller_data(response: Response) -> Result<ControllerData, Error> {
+    match response {
+        Response::ControllerData(c) => {
+            debug!("controller loaded: {:?}", c);
+            Ok(*c)
+        }
+        r => Err(unexpected(r)),
+    }
+}
+
+pub(crate) fn profile_list(response: Response) -> Result<Vec<String>, Error> {
+    match response {
+        Response::ProfileList(profiles) => Ok(profiles),
+        r => Err(unexpected(r)),
+    }
+}
+
+impl Notification {
+    fn from_packet_type(packet_type: u32) -> Option<Self> {
+        match packet_type {
+            NET_PACKET_ID_DEVICE_LIST_UPDATED => Some(Notification::DeviceListUpdated),
+            _ => None,
+        }
+    }
+}
+
+/// Server connection with the data, read ahead while polling notifications
+struct Connection {
+    transport: Box<dyn Transport>,
+    buf: Vec<u8>,
+}
+
+impl Connection {
+    fn new(transport: Box<dyn Transport>) -> Self {
+        Self {
+            transport,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Reads all the data available without blocking
+    fn read_available(&mut self) -> Result<(), io::Error> {
+        let mut buf = [0u8; 4096];
+        self.transport.set_nonblocking(true)?;
+        let result = loop {
+            match self.transport.read(&mut buf) {
+                // closed connection, leave it for the next call
+                Ok(0) => break Ok(()),
+                Ok(n) => self.buf.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.transport.set_nonblocking(false)?;
+        result
+    }
+
+    /// Checks if a complete packet has been read ahead
+    fn has_packet(&self) -> bool {
+        if self.buf.len() < PacketHeader::SIZE {
+            return false;
+        }
+        match PacketHeader::unpack(&self.buf[..PacketHeader::SIZE]) {
+            Ok(header) => self.buf.len() - PacketHeader::SIZE >= header.len as usize,
+            // let read_packet report the error
+            Err(_) => true,
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            self.transport.read(buf)
+        } else {
+            let len = std::cmp::min(buf.len(), self.buf.len());
+            buf[..len].copy_from_slice(&self.buf[..len]);
+            self.buf.drain(..len);
+            Ok(len)
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.transport.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.transport.flush()
+    }
+}
+
+fn read_packet<R: Read + ?Sized>(
+    stream: &mut R,
+    capture: Option<&mut Capture>,
+) -> Result<(u32, u32, Vec<u8>), Error> {
+    let mut buf = [0u8; PacketHeader::SIZE];
+    stream.read_exact(&mut buf)?;
+    let header = PacketHeader::unpack(&buf)?;
+    let mut data = vec![0u8; header.len as usize];
+    stream.read_exact(&mut data)?;
+    if let Some(capture) = capture {
+        record(
+            capture,
+            Direction::Received,
+            &encode_packet(header.device_id, header.packet_type, &data),
+        );
+    }
+    Ok((header.device_id, header.packet_type, data))
+}
+
+pub(crate) fn record(capture: &mut Capture, direction: Direction, packet: &[u8]) {
+    if let Err(e) = capture.write(direction, packet) {
+        error!("Unable to write capture: {}", e);
+    }
+}
+
+impl Default for OpenRGBClient {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OpenRGBClient {
     pub fn new() -> Self {
         Self {
             stream: None,
-            path: String::new(),
+            capture: None,
+            notifications: Vec::new(),
+            connector: transport::connector(""),
             retries: 3,
             timeout: Duration::from_secs(2),
             controllers: Vec::new(),
             server_protocol: None,
+            protocol: None,
         }
     }
 
+    /// Sets the server path: host:port, host name, IP address or "unix:/path/to/socket"
     pub fn set_path(&mut self, path: &str) {
-        self.path = path.to_owned();
+        self.set_connector(transport::connector(path));
+    }
+
+    /// Sets a custom transport connector
+    pub fn set_connector(&mut self, connector: Box<dyn Connector>) {
+        self.connector = connector;
         self.stream = None;
-        debug!("ORGB server path set: {}", self.path);
+        debug!("ORGB server path set: {}", self.connector);
+    }
+
+    /// Sets the I/O timeout, including the open connection
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        if let Some(ref mut stream) = self.stream {
+            if stream.transport.set_timeout(timeout).is_err() {
+                // reconnect on the next call
+                self.stream = None;
+            }
+        }
     }
 
-    fn get_stream(&mut self) -> Result<&mut TcpStream, io::Error> {
+    /// Writes all packets, sent and received, to the capture file
+    pub fn set_capture(&mut self, path: &str) -> Result<(), Error> {
+        self.capture = Some(Capture::create(path)?);
+        debug!("capturing packets to {}", path);
+        Ok(())
+    }
+
+    fn get_stream(&mut self) -> Result<&mut Connection, Error> {
         match self.stream {
             Some(ref mut v) => Ok(v),
             None => {
-                let stream = match TcpStream::connect(&self.path) {
+                let mut transport = match self.connector.connect(self.timeout) {
                     Ok(v) => v,
                     Err(e) => {
-                        error!("ORGB server {} error: {}", &self.path, e);
-                        return Err(e);
+                        error!("ORGB server {} error: {}", self.connector, e);
+                        return Err(e.into());
                     }
                 };
-                stream.set_read_timeout(Some(self.timeout))?;
-                stream.set_write_timeout(Some(self.timeout))?;
-                self.stream = Some(stream);
-                debug!("ORGB server connected: {}", self.path);
+                transport.set_timeout(self.timeout)?;
+                self.stream = Some(Connection::new(transport));
+                debug!("ORGB server connected: {}", self.connector);
                 Ok(self.stream.as_mut().unwrap())
             }
         }
@@ -305,14 +1517,24 @@ impl OpenRGBClient {
         device_id: u32,
         packet_type: u32,
         data: &[u8],
-    ) -> Result<Option<Vec<u8>>, std::io::Error> {
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.call_with_retries(device_id, packet_type, data, self.retries)
+    }
+
+    fn call_with_retries(
+        &mut self,
+        device_id: u32,
+        packet_type: u32,
+        data: &[u8],
+        retries: u8,
+    ) -> Result<Option<Vec<u8>>, Error> {
         let mut attempt = 0;
         loop {
             match self._call(device_id, packet_type, data) {
                 Ok(v) => return Ok(v),
                 Err(e) => {
                     attempt += 1;
-                    if attempt > self.retries {
+                    if attempt > retries {
                         return Err(e);
                     } else {
                         self.stream = None;
@@ -327,182 +1549,477 @@ impl OpenRGBClient {
         device_id: u32,
         packet_type: u32,
         data: &[u8],
-    ) -> Result<Option<Vec<u8>>, std::io::Error> {
-        let stream = self.get_stream()?;
-        let mut request = Vec::new();
-        request.extend_from_slice(&HEADER);
-        request.extend_from_slice(&device_id.to_le_bytes());
-        request.extend_from_slice(&packet_type.to_le_bytes());
-        request.extend_from_slice(&(data.len() as u32).to_le_bytes());
-        request.extend_from_slice(data);
-        stream.write(&request)?;
-        if packet_type == REQ_SET_CLIENT_NAME || packet_type == REQ_RGBCONTROLLER_UPDATELEDS {
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.get_stream()?;
+        let stream = self.stream.as_mut().unwrap();
+        let packet = encode_packet(device_id, packet_type, data);
+        stream.write_all(&packet)?;
+        if let Some(ref mut capture) = self.capture {
+            record(capture, Direction::Sent, &packet);
+        }
+        if !Request::has_response(packet_type) {
             return Ok(None);
         }
-        let mut buf = [0u8; 16];
-        stream.read_exact(&mut buf)?;
-        let r_device_id = u32::from_le_bytes(try_data!(buf[4..8]));
-        let r_packet_type = u32::from_le_bytes(try_data!(buf[8..12]));
-        if buf[..4] != HEADER || r_device_id != device_id || r_packet_type != packet_type {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid server response",
-            ));
+        let mut notifications = Vec::new();
+        let result = loop {
+            let (r_device_id, r_packet_type, response) =
+                match read_packet(stream, self.capture.as_mut()) {
+                    Ok(v) => v,
+                    Err(e) => break Err(e),
+                };
+            if let Some(n) = Notification::from_packet_type(r_packet_type) {
+                debug!("server notification: {:?}", n);
+                notifications.push(n);
+                continue;
+            }
+            if r_device_id != device_id || r_packet_type != packet_type {
+                break Err(Error::Protocol(format!(
+                    "response device {} packet {} received, expected device {} packet {}",
+                    r_device_id, r_packet_type, device_id, packet_type
+                )));
+            }
+            break Ok(Some(response));
+        };
+        self.notifications.append(&mut notifications);
+        result
+    }
+
+    /// Reads notifications, sent by the server, without blocking. Returns all notifications
+    /// received since the previous poll, including ones read during regular calls
+    pub fn poll_notifications(&mut self) -> Result<Vec<Notification>, Error> {
+        if let Some(stream) = self.stream.as_mut() {
+            let mut notifications = Vec::new();
+            if let Err(e) = stream.read_available() {
+                self.stream = None;
+                return Err(e.into());
+            }
+            let result = loop {
+                // incomplete packets are left for the next call
+                if !stream.has_packet() {
+                    break Ok(());
+                }
+                match read_packet(stream, self.capture.as_mut()) {
+                    Ok((_, packet_type, _)) => match Notification::from_packet_type(packet_type) {
+                        Some(n) => {
+                            debug!("server notification: {:?}", n);
+                            notifications.push(n);
+                        }
+                        None => {
+                            break Err(Error::Protocol(format!(
+                                "unexpected packet {} received",
+                                packet_type
+                            )))
+                        }
+                    },
+                    Err(e) => break Err(e),
+                }
+            };
+            self.notifications.append(&mut notifications);
+            if let Err(e) = result {
+                self.stream = None;
+                return Err(e);
+            }
         }
-        let r_len = u32::from_le_bytes(try_data!(buf[12..16]));
-        let mut response = vec![0u8; r_len as usize];
-        stream.read_exact(&mut response)?;
-        Ok(Some(response))
+        Ok(std::mem::take(&mut self.notifications))
     }
 
-    pub fn load(&mut self) -> Result<(), io::Error> {
-        self.controllers.clear();
-        let data = self.call(
-            0,
-            REQ_REQUEST_PROTOCOL_VERSION,
-            &PROTOCOL_VERSION.to_le_bytes(),
+    /// Sends the request, using the negotiated protocol version
+    pub fn request(
+        &mut self,
+        device_id: u32,
+        request: &Request,
+    ) -> Result<Option<Response>, Error> {
+        self.request_with_retries(device_id, request, self.retries)
+    }
+
+    fn request_with_retries(
+        &mut self,
+        device_id: u32,
+        request: &Request,
+        retries: u8,
+    ) -> Result<Option<Response>, Error> {
+        let protocol = self.protocol.unwrap_or_default();
+        let data = self.call_with_retries(
+            device_id,
+            request.packet_type(),
+            &request.pack(protocol)?,
+            retries,
         )?;
-        let server_protocol_version = u32::from_le_bytes(try_data!(data.unwrap()));
-        if server_protocol_version != PROTOCOL_VERSION {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Server protocol unsupported",
-            ));
-        }
-        self.server_protocol = Some(server_protocol_version);
-        let mut buf = Vec::new();
-        buf.extend_from_slice(CLIENT_NAME.as_bytes());
-        buf.push(32);
-        buf.extend_from_slice(VERSION.as_bytes());
-        buf.push(0);
-        self.call(0, REQ_SET_CLIENT_NAME, &buf)?;
-        let data = self.call(0, REQ_REQUEST_CONTROLLER_COUNT, &buf)?;
-        let controller_count = u32::from_le_bytes(try_data!(data.unwrap()));
-        debug!("{} controller(s) found", controller_count);
-        for i in 0..controller_count {
-            let data = self
-                .call(
-                    i,
-                    REQ_REQUEST_CONTROLLER_DATA,
-                    &PROTOCOL_VERSION.to_le_bytes(),
-                )?;
-            let c = ControllerData::unpack(i, &data.unwrap())?;
-            debug!("controller loaded: {:?}", c);
+        if let Some(c) = self.controllers.iter_mut().find(|c| c.id == device_id) {
+            c.apply_colors(request);
+        }
+        decode_response(device_id, request, protocol, data)
+    }
+
+    fn query(&mut self, device_id: u32, request: &Request) -> Result<Response, Error> {
+        self.request(device_id, request)?.ok_or_else(no_response)
+    }
+
+    pub fn load(&mut self) -> Result<(), Error> {
+        self.controllers.clear();
+        self.protocol = None;
+        // the probe is not retried, no reply means protocol 0
+        let result = self
+            .request_with_retries(0, &version_request(), 0)
+            .and_then(|r| r.ok_or_else(no_response));
+        if result.is_err() {
+            self.stream = None;
+        }
+        let (server_protocol, protocol) = negotiate_protocol(result)?;
+        self.server_protocol = Some(server_protocol);
+        self.protocol = Some(protocol);
+        self.request(0, &client_name_request())?;
+        let count = controller_count(self.query(0, &Request::RequestControllerCount)?)?;
+        for i in 0..count {
+            let c = controller_data(self.query(i, &Request::RequestControllerData { protocol })?)?;
             self.controllers.push(c);
         }
         Ok(())
     }
 
-    pub fn reload(&mut self) -> Result<(), io::Error> {
+    pub fn reload(&mut self) -> Result<(), Error> {
         self.stream = None;
+        self.notifications.clear();
         debug!("reloading");
         self.load()
     }
 
-    pub fn set_color_by_id(
-        &mut self,
-        controller_id: u32,
-        color: &RGBColor,
-    ) -> Result<(), io::Error> {
+    pub fn set_color_by_id(&mut self, controller_id: u32, color: &RGBColor) -> Result<(), Error> {
+        self.set_colors_by_id(controller_id, |c| vec![*color; c.leds.len()])
+    }
+
+    /// Sets LED colors of the controller, the frame is produced by the closure and truncated to
+    /// the number of LEDs
+    pub fn set_colors_by_id<F>(&mut self, controller_id: u32, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
         let mut to_set = Vec::new();
         for c in &self.controllers {
             if c.id == controller_id {
-                to_set.push(ControllerLedSetCommand {
-                    controller_id: c.id,
-                    end: c.leds.len() as u16,
-                });
+                to_set.push(ControllerLedSetCommand::new(c, f(c)));
                 break;
             }
         }
-        check_batch!(to_set);
-        self.set_color_for_controllers(&to_set, color)
+        check_batch!(to_set, "id {}", controller_id);
+        self.set_colors_for_controllers(&to_set)
     }
 
     pub fn set_color_by_name(
         &mut self,
         controller_name: &str,
         color: &RGBColor,
-    ) -> Result<(), io::Error> {
+    ) -> Result<(), Error> {
+        self.set_colors_by_name(controller_name, |c| vec![*color; c.leds.len()])
+    }
+
+    /// Sets LED colors of all controllers with the given name, the frame for each controller is
+    /// produced by the closure
+    pub fn set_colors_by_name<F>(&mut self, controller_name: &str, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
         let mut to_set = Vec::new();
         for c in &self.controllers {
             if c.name == controller_name {
-                to_set.push(ControllerLedSetCommand {
-                    controller_id: c.id,
-                    end: c.leds.len() as u16,
-                });
+                to_set.push(ControllerLedSetCommand::new(c, f(c)));
             }
         }
-        check_batch!(to_set);
-        self.set_color_for_controllers(&to_set, color)
+        check_batch!(to_set, "name {:?}", controller_name);
+        self.set_colors_for_controllers(&to_set)
     }
 
     pub fn set_color_by_device_types(
         &mut self,
-        device_types: &Vec<u32>,
+        device_types: &[DeviceType],
         color: &RGBColor,
-    ) -> Result<(), io::Error> {
+    ) -> Result<(), Error> {
+        self.set_colors_by_device_types(device_types, |c| vec![*color; c.leds.len()])
+    }
+
+    /// Sets LED colors of all controllers of the given types, the frame for each controller is
+    /// produced by the closure
+    pub fn set_colors_by_device_types<F>(
+        &mut self,
+        device_types: &[DeviceType],
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
         let mut found = false;
         for d in device_types {
-            match self.set_color_by_device_type(*d, color) {
+            match self.set_colors_by_device_type(*d, &mut f) {
                 Ok(_) => found = true,
-                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(Error::ControllerNotFound(_)) => {}
                 Err(e) => return Err(e),
             }
         }
         match found {
             true => Ok(()),
-            false => Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                ERR_CONTROLLER_NOT_FOUND,
-            )),
+            false => Err(Error::ControllerNotFound(format!(
+                "device types {}",
+                device_types
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ))),
         }
     }
 
     pub fn set_color_by_device_type(
         &mut self,
-        device_type: u32,
+        device_type: DeviceType,
         color: &RGBColor,
-    ) -> Result<(), io::Error> {
+    ) -> Result<(), Error> {
+        self.set_colors_by_device_type(device_type, |c| vec![*color; c.leds.len()])
+    }
+
+    /// Sets LED colors of all controllers of the given type, the frame for each controller is
+    /// produced by the closure
+    pub fn set_colors_by_device_type<F>(
+        &mut self,
+        device_type: DeviceType,
+        mut f: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
         let mut to_set = Vec::new();
         for c in &self.controllers {
             if c.device_type == device_type {
-                to_set.push(ControllerLedSetCommand {
-                    controller_id: c.id,
-                    end: c.leds.len() as u16,
-                });
+                to_set.push(ControllerLedSetCommand::new(c, f(c)));
             }
         }
-        check_batch!(to_set);
-        self.set_color_for_controllers(&to_set, color)
+        check_batch!(to_set, "device type {}", device_type);
+        self.set_colors_for_controllers(&to_set)
+    }
+
+    pub fn set_color_by_selector(
+        &mut self,
+        selector: &Selector,
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        self.set_colors_by_selector(selector, |c| vec![*color; c.leds.len()])
+    }
+
+    /// Sets LED colors of all controllers, matching the selector, the frame for each controller
+    /// is produced by the closure
+    pub fn set_colors_by_selector<F>(&mut self, selector: &Selector, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
+        let mut to_set = Vec::new();
+        for c in selector.select(&self.controllers) {
+            to_set.push(ControllerLedSetCommand::new(c, f(c)));
+        }
+        check_batch!(to_set, "{}", selector);
+        self.set_colors_for_controllers(&to_set)
+    }
+
+    pub fn set_color(&mut self, color: &RGBColor) -> Result<(), Error> {
+        self.set_colors(|c| vec![*color; c.leds.len()])
     }
 
-    pub fn set_color(&mut self, color: &RGBColor) -> Result<(), io::Error> {
+    /// Sets LED colors of all controllers, the frame for each controller is produced by the
+    /// closure
+    pub fn set_colors<F>(&mut self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&ControllerData) -> Vec<RGBColor>,
+    {
         let mut to_set = Vec::new();
         for c in &self.controllers {
-            to_set.push(ControllerLedSetCommand {
-                controller_id: c.id,
-                end: c.leds.len() as u16,
-            });
+            to_set.push(ControllerLedSetCommand::new(c, f(c)));
+        }
+        self.set_colors_for_controllers(&to_set)
+    }
+
+    fn get_controller(&self, controller_id: u32) -> Result<&ControllerData, Error> {
+        self.controllers
+            .iter()
+            .find(|c| c.id == controller_id)
+            .ok_or_else(|| Error::ControllerNotFound(format!("id {}", controller_id)))
+    }
+
+    /// Sets colors of a single zone. If less colors than zone LEDs are given, the rest of LEDs
+    /// are left untouched
+    pub fn set_zone_colors(
+        &mut self,
+        controller_id: u32,
+        zone_id: u32,
+        colors: &[RGBColor],
+    ) -> Result<(), Error> {
+        let controller = self.get_controller(controller_id)?;
+        let zone = controller
+            .zones
+            .get(zone_id as usize)
+            .ok_or_else(|| Error::NotFound(format!("zone {}", zone_id)))?;
+        let num_colors = std::cmp::min(colors.len(), zone.leds_count as usize);
+        self.request(
+            controller_id,
+            &Request::UpdateZoneLeds {
+                zone_id,
+                colors: colors[..num_colors].to_vec(),
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn set_led_color(
+        &mut self,
+        controller_id: u32,
+        led_id: u32,
+        color: &RGBColor,
+    ) -> Result<(), Error> {
+        let controller = self.get_controller(controller_id)?;
+        if led_id as usize >= controller.leds.len() {
+            return Err(Error::NotFound(format!("LED {}", led_id)));
         }
-        self.set_color_for_controllers(&to_set, color)
+        self.request(
+            controller_id,
+            &Request::UpdateSingleLed {
+                led_id,
+                color: *color,
+            },
+        )?;
+        Ok(())
     }
 
-    fn set_color_for_controllers(
+    /// Sets color of the LEDs, matching any of the selectors, the rest of LEDs are left
+    /// untouched. The current colors of controllers with partially selected LEDs are read from
+    /// the server first, to send a single frame per controller
+    pub fn set_leds_color(
         &mut self,
-        cmd: &Vec<ControllerLedSetCommand>,
+        selectors: &[LedSelector],
         color: &RGBColor,
-    ) -> Result<(), io::Error> {
+    ) -> Result<(), Error> {
+        let protocol = self.protocol.unwrap_or_default();
+        for controller_id in partially_selected(selectors, &self.controllers) {
+            let response =
+                self.query(controller_id, &Request::RequestControllerData { protocol })?;
+            update_colors(self.get_controller_mut(controller_id)?, response)?;
+        }
+        for (controller_id, request) in led_requests(selectors, &self.controllers, color)? {
+            self.request(controller_id, &request)?;
+        }
+        Ok(())
+    }
+
+    fn get_controller_mut(&mut self, controller_id: u32) -> Result<&mut ControllerData, Error> {
+        self.controllers
+            .iter_mut()
+            .find(|c| c.id == controller_id)
+            .ok_or_else(|| Error::ControllerNotFound(format!("id {}", controller_id)))
+    }
+
+    /// Switches the controller to the mode, keeping the mode parameters loaded from the server
+    pub fn set_mode(&mut self, controller_id: u32, mode_id: u32) -> Result<(), Error> {
+        let mode = self
+            .get_controller(controller_id)?
+            .modes
+            .get(mode_id as usize)
+            .ok_or_else(|| Error::NotFound(format!("mode {}", mode_id)))?
+            .clone();
+        self.update_mode(controller_id, mode_id, &mode)
+    }
+
+    /// Switches the controller to the mode with the given parameters (speed, colors etc.)
+    pub fn update_mode(
+        &mut self,
+        controller_id: u32,
+        mode_id: u32,
+        mode: &ModeData,
+    ) -> Result<(), Error> {
+        self.check_mode(controller_id, mode_id)?;
+        self.request(
+            controller_id,
+            &Request::UpdateMode {
+                mode_id,
+                mode: mode.clone(),
+            },
+        )?;
+        let controller = self.get_controller_mut(controller_id)?;
+        controller.modes[mode_id as usize] = mode.clone();
+        controller.active_mode = mode_id as i32;
+        Ok(())
+    }
+
+    /// Saves the mode to the device memory (requires protocol 3+)
+    pub fn save_mode(&mut self, controller_id: u32, mode_id: u32) -> Result<(), Error> {
+        self.check_protocol(3)?;
+        let mode = self
+            .get_controller(controller_id)?
+            .modes
+            .get(mode_id as usize)
+            .ok_or_else(|| Error::NotFound(format!("mode {}", mode_id)))?
+            .clone();
+        self.request(controller_id, &Request::SaveMode { mode_id, mode })?;
+        Ok(())
+    }
+
+    /// Switches the controller to the mode, which accepts colors from the SDK (Direct, Custom or
+    /// Static)
+    pub fn set_custom_mode(&mut self, controller_id: u32) -> Result<(), Error> {
+        self.get_controller(controller_id)?;
+        self.request(controller_id, &Request::SetCustomMode)?;
+        let controller = self.get_controller_mut(controller_id)?;
+        if let Some(mode_id) = controller.custom_mode() {
+            controller.active_mode = mode_id as i32;
+        }
+        Ok(())
+    }
+
+    fn check_mode(&self, controller_id: u32, mode_id: u32) -> Result<(), Error> {
+        if mode_id as usize >= self.get_controller(controller_id)?.modes.len() {
+            Err(Error::NotFound(format!("mode {}", mode_id)))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_protocol(&self, required: u32) -> Result<(), Error> {
+        let negotiated = self.protocol.unwrap_or_default();
+        if negotiated < required {
+            Err(Error::UnsupportedVersion {
+                required,
+                negotiated,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns names of the profiles, stored on the server
+    pub fn profiles(&mut self) -> Result<Vec<String>, Error> {
+        self.check_protocol(2)?;
+        profile_list(self.query(0, &Request::RequestProfileList)?)
+    }
+
+    /// Saves the current state of all devices to the server profile
+    pub fn save_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.profile_call(Request::SaveProfile(name.to_owned()))
+    }
+
+    /// Loads the server profile. The controllers should be reloaded to get the actual modes and
+    /// colors
+    pub fn load_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.profile_call(Request::LoadProfile(name.to_owned()))
+    }
+
+    pub fn delete_profile(&mut self, name: &str) -> Result<(), Error> {
+        self.profile_call(Request::DeleteProfile(name.to_owned()))
+    }
+
+    fn profile_call(&mut self, request: Request) -> Result<(), Error> {
+        self.check_protocol(2)?;
+        self.request(0, &request)?;
+        Ok(())
+    }
+
+    fn set_colors_for_controllers(&mut self, cmd: &[ControllerLedSetCommand]) -> Result<(), Error> {
         for c in cmd {
-            let mut data: Vec<u8> = Vec::new();
-            data.extend_from_slice(&((4 * c.end + 6) as u32).to_le_bytes());
-            data.extend_from_slice(&c.end.to_le_bytes());
-            for _ in 0..c.end {
-                data.push(color.red);
-                data.push(color.green);
-                data.push(color.blue);
-                data.push(0x00); // X
-            }
-            self.call(c.controller_id, REQ_RGBCONTROLLER_UPDATELEDS, &data)?;
+            self.request(c.controller_id, &Request::UpdateLeds(c.colors.clone()))?;
         }
         Ok(())
     }
@@ -0,0 +1,323 @@
+//! In-process mock OpenRGB SDK server for tests. Serves fake controllers over TCP and records
+//! every request it receives
+use crate::transport::{duplex, Connector, Transport};
+use crate::{
+    read_packet, ControllerData, ControllerMetaData, DeviceType, Error, LedData, ModeData,
+    RGBColor, Request, Response, ZoneData, MODE_COLORS_NONE, MODE_COLORS_PER_LED,
+    MODE_FLAG_HAS_PER_LED_COLOR, MODE_FLAG_HAS_SPEED, PROTOCOL_VERSION, ZONE_TYPE_LINEAR,
+};
+use log::debug;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+struct MockState {
+    protocol: u32,
+    controllers: Vec<ControllerData>,
+    profiles: Vec<String>,
+    requests: Vec<(u32, Request)>,
+    clients: Vec<Box<dyn Transport>>,
+}
+
+pub struct MockServer {
+    addr: String,
+    state: Arc<Mutex<MockState>>,
+    stopped: Arc<AtomicBool>,
+}
+
+/// Connects clients to the mock server with in-memory streams
+pub struct MockConnector {
+    state: Arc<Mutex<MockState>>,
+}
+
+/// Builds a controller with Direct and Rainbow modes and a single linear zone
+pub fn controller(name: &str, device_type: DeviceType, num_leds: usize) -> ControllerData {
+    let mode = |name: &str, value, flags, color_mode| ModeData {
+        name: name.to_owned(),
+        value,
+        flags,
+        speed_min: 0,
+        speed_max: 0,
+        brightness_min: 0,
+        brightness_max: 0,
+        colors_min: 0,
+        colors_max: 0,
+        speed: 0,
+        brightness: 0,
+        direction: 0,
+        color_mode,
+        colors: Vec::new(),
+    };
+    ControllerData {
+        id: 0,
+        name: name.to_owned(),
+        metadata: ControllerMetaData {
+            vendor: "Mock".to_owned(),
+            description: format!("Mock {}", device_type),
+            version: crate::VERSION.to_owned(),
+            serial: String::new(),
+            location: "mock".to_owned(),
+        },
+        device_type,
+        modes: vec![
+            mode("Rainbow", 1, MODE_FLAG_HAS_SPEED, MODE_COLORS_NONE),
+            mode(
+                "Direct",
+                0,
+                MODE_FLAG_HAS_PER_LED_COLOR,
+                MODE_COLORS_PER_LED,
+            ),
+        ],
+        active_mode: 0,
+        zones: vec![ZoneData {
+            name: "LEDs".to_owned(),
+            zone_type: ZONE_TYPE_LINEAR,
+            leds_min: num_leds as u32,
+            leds_max: num_leds as u32,
+            leds_count: num_leds as u32,
+            matrix_map: None,
+            segments: Vec::new(),
+        }],
+        leds: (0..num_leds)
+            .map(|i| LedData {
+                name: format!("LED {}", i + 1),
+                value: i as u32,
+            })
+            .collect(),
+        colors: vec![RGBColor::black(); num_leds],
+    }
+}
+
+impl MockServer {
+    /// Starts the server on a random local port. Controller ids are set to their positions
+    pub fn start(controllers: Vec<ControllerData>) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?.to_string();
+        let state = Arc::new(Mutex::new(MockState {
+            protocol: PROTOCOL_VERSION,
+            controllers: Vec::new(),
+            profiles: Vec::new(),
+            requests: Vec::new(),
+            clients: Vec::new(),
+        }));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let server = Self {
+            addr,
+            state: state.clone(),
+            stopped: stopped.clone(),
+        };
+        server.state.lock().unwrap().controllers = renumber(controllers);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    if let Ok(s) = stream.try_clone() {
+                        state.lock().unwrap().clients.push(Box::new(s));
+                    }
+                    let state = state.clone();
+                    thread::spawn(move || {
+                        let _ = serve(stream, state);
+                    });
+                }
+            }
+        });
+        debug!("mock server started: {}", server.addr);
+        Ok(server)
+    }
+
+    /// Server address (host:port)
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Connector for in-memory client connections, an alternative to TCP
+    pub fn connector(&self) -> Box<dyn Connector> {
+        Box::new(MockConnector {
+            state: self.state.clone(),
+        })
+    }
+
+    /// Sets the protocol version reported by the server. Servers with protocol 0 do not reply to
+    /// the version request
+    pub fn set_protocol(&self, protocol: u32) {
+        self.state.lock().unwrap().protocol = protocol;
+    }
+
+    /// Replaces the controllers and sends DeviceListUpdated to all connected clients
+    pub fn set_controllers(&self, controllers: Vec<ControllerData>) {
+        let mut state = self.state.lock().unwrap();
+        state.controllers = renumber(controllers);
+        // the notification has no data, so it is always packed
+        let packet = Response::DeviceListUpdated
+            .encode(0, state.protocol)
+            .unwrap();
+        state
+            .clients
+            .retain_mut(|client| client.write_all(&packet).is_ok());
+    }
+
+    /// Current state of the controllers, with all updates applied
+    pub fn controllers(&self) -> Vec<ControllerData> {
+        self.state.lock().unwrap().controllers.clone()
+    }
+
+    pub fn set_profiles(&self, profiles: &[&str]) {
+        self.state.lock().unwrap().profiles = profiles.iter().map(|v| (*v).to_owned()).collect();
+    }
+
+    pub fn profiles(&self) -> Vec<String> {
+        self.state.lock().unwrap().profiles.clone()
+    }
+
+    /// All requests received, with device ids
+    pub fn requests(&self) -> Vec<(u32, Request)> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Colors of all UpdateLeds requests received, with device ids
+    pub fn led_updates(&self) -> Vec<(u32, Vec<RGBColor>)> {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .iter()
+            .filter_map(|(device_id, request)| match request {
+                Request::UpdateLeds(colors) => Some((*device_id, colors.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn clear_requests(&self) {
+        self.state.lock().unwrap().requests.clear();
+    }
+
+    /// Drops all client connections, clients are expected to reconnect
+    pub fn disconnect(&self) {
+        for client in self.state.lock().unwrap().clients.drain(..) {
+            let _ = client.shutdown();
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // wake up the listener
+        let _ = TcpStream::connect(&self.addr);
+        for client in &self.state.lock().unwrap().clients {
+            let _ = client.shutdown();
+        }
+    }
+}
+
+impl fmt::Display for MockConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mock")
+    }
+}
+
+impl Connector for MockConnector {
+    fn connect(&self, _timeout: Duration) -> io::Result<Box<dyn Transport>> {
+        let (client, server) = duplex();
+        self.state
+            .lock()
+            .unwrap()
+            .clients
+            .push(Box::new(server.try_clone()?));
+        let state = self.state.clone();
+        thread::spawn(move || {
+            let _ = serve(server, state);
+        });
+        Ok(Box::new(client))
+    }
+}
+
+fn renumber(mut controllers: Vec<ControllerData>) -> Vec<ControllerData> {
+    for (i, c) in controllers.iter_mut().enumerate() {
+        c.id = i as u32;
+    }
+    controllers
+}
+
+fn serve<S: io::Read + Write>(mut stream: S, state: Arc<Mutex<MockState>>) -> Result<(), Error> {
+    // the protocol, negotiated with the client
+    let mut protocol = 0;
+    loop {
+        let (device_id, packet_type, data) = read_packet(&mut stream, None)?;
+        let mut state = state.lock().unwrap();
+        let request = Request::unpack(packet_type, protocol, &data)?;
+        debug!("mock server request: {} {:?}", device_id, request);
+        state.requests.push((device_id, request.clone()));
+        // responses are packed with the negotiated protocol, controller data - with the
+        // requested one
+        let response = match request {
+            Request::RequestProtocolVersion(v) => {
+                if state.protocol == 0 {
+                    None
+                } else {
+                    protocol = std::cmp::min(v, state.protocol);
+                    Some((Response::ProtocolVersion(state.protocol), protocol))
+                }
+            }
+            Request::RequestControllerCount => Some((
+                Response::ControllerCount(state.controllers.len() as u32),
+                protocol,
+            )),
+            Request::RequestControllerData { protocol: p } => state
+                .controllers
+                .get(device_id as usize)
+                .map(|c| (Response::ControllerData(Box::new(c.clone())), p)),
+            Request::RequestProfileList => {
+                Some((Response::ProfileList(state.profiles.clone()), protocol))
+            }
+            Request::SaveProfile(name) => {
+                if !state.profiles.contains(&name) {
+                    state.profiles.push(name);
+                }
+                None
+            }
+            Request::DeleteProfile(name) => {
+                state.profiles.retain(|p| *p != name);
+                None
+            }
+            Request::SetClientName(_) | Request::LoadProfile(_) => None,
+            request => {
+                if let Some(c) = state.controllers.get_mut(device_id as usize) {
+                    apply(c, request);
+                }
+                None
+            }
+        };
+        if let Some((r, p)) = response {
+            stream.write_all(&r.encode(device_id, p)?)?;
+        }
+    }
+}
+
+/// Applies controller requests to the controller data, the way the real server does
+fn apply(c: &mut ControllerData, request: Request) {
+    match request {
+        Request::UpdateLeds(_)
+        | Request::UpdateZoneLeds { .. }
+        | Request::UpdateSingleLed { .. } => c.apply_colors(&request),
+        Request::SetCustomMode => {
+            if let Some(mode_id) = c.custom_mode() {
+                c.active_mode = mode_id as i32;
+            }
+        }
+        Request::UpdateMode { mode_id, mode } => {
+            if let Some(m) = c.modes.get_mut(mode_id as usize) {
+                *m = mode;
+                c.active_mode = mode_id as i32;
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,588 @@
+//! Monitoring engine: samples a metric, maps its value to a color and applies the color to the
+//! output
+use crate::band::{Band, Fill};
+use crate::gradient::Gradient;
+use crate::handle::ClientHandle;
+use crate::selector::{LedSelector, Selector};
+use crate::{ControllerData, Error, Notification, OpenRGBClient, RGBColor};
+use colored::Colorize;
+use cpu_monitor::CpuInstant;
+use log::{debug, error, info};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often animated colors (effects) are updated
+pub const ANIMATION_STEP: Duration = Duration::from_millis(50);
+
+/// Source of the monitored value
+pub trait Metric: Send {
+    /// Samples the metric during the interval (blocks), returns the value in percents
+    fn sample(&mut self, interval: Duration) -> Result<u8, Error>;
+}
+
+/// Maps metric values (percents) to colors
+pub trait ColorMap: Send {
+    fn color(&self, value: u8) -> RGBColor;
+    /// The color at the time since the monitor start, for animated colors. Static by default
+    fn color_at_time(&self, value: u8, _time: Duration) -> RGBColor {
+        self.color(value)
+    }
+    /// True if colors change with time, so they must be updated between the samples
+    fn is_animated(&self) -> bool {
+        false
+    }
+}
+
+impl<F> ColorMap for F
+where
+    F: Fn(u8) -> RGBColor + Send,
+{
+    fn color(&self, value: u8) -> RGBColor {
+        self(value)
+    }
+}
+
+/// Displays the colors
+pub trait Output: Send {
+    fn set_color(&mut self, color: &RGBColor) -> Result<(), Error>;
+    /// Called when the monitor is stopped, turns LEDs off by default
+    fn suspend(&mut self) -> Result<(), Error> {
+        self.set_color(&RGBColor::black())
+    }
+    /// Called after the devices are reloaded (e.g. hot-plugged), to set them up again
+    fn reload(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Checks for external changes (e.g. hot-plugged devices), returns true if the current color
+    /// must be applied again
+    fn refresh(&mut self) -> Result<bool, Error> {
+        Ok(false)
+    }
+    /// Called before exit
+    fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// CPU load, all cores
+#[derive(Default)]
+pub struct CpuLoad {}
+
+impl CpuLoad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Metric for CpuLoad {
+    fn sample(&mut self, interval: Duration) -> Result<u8, Error> {
+        let start = CpuInstant::now()?;
+        thread::sleep(interval);
+        let end = CpuInstant::now()?;
+        let load = ((end - start).non_idle() * 100.) as u8;
+        debug!("CPU load: {}", format!("{}%", &load).cyan());
+        Ok(load)
+    }
+}
+
+/// Maps values to gradient colors, values, covered by load bands, get colors of the bands
+pub struct GradientColorMap {
+    pub gradient: Gradient,
+    /// Checked in order, the first band, containing the value, is used
+    pub bands: Vec<Band>,
+}
+
+impl GradientColorMap {
+    pub fn new(gradient: Gradient) -> Self {
+        Self {
+            gradient,
+            bands: Vec::new(),
+        }
+    }
+}
+
+impl Default for GradientColorMap {
+    fn default() -> Self {
+        Self::new(Gradient::rainbow())
+    }
+}
+
+impl ColorMap for GradientColorMap {
+    fn color(&self, value: u8) -> RGBColor {
+        self.color_at_time(value, Duration::ZERO)
+    }
+
+    fn color_at_time(&self, value: u8, time: Duration) -> RGBColor {
+        match self.bands.iter().find(|b| b.contains(value)) {
+            Some(band) => band.color(value, time),
+            None => self.gradient.color_at(value as f32),
+        }
+    }
+
+    fn is_animated(&self) -> bool {
+        self.bands
+            .iter()
+            .any(|b| matches!(b.fill(), Fill::Effect(_)))
+    }
+}
+
+impl ColorMap for Gradient {
+    fn color(&self, value: u8) -> RGBColor {
+        self.color_at(value as f32)
+    }
+}
+
+/// Controller identity, which is kept when devices are hot-plugged, unlike ids. Controllers with
+/// the same serial, location and name are told apart by their order
+#[derive(Debug, Clone, PartialEq)]
+struct ControllerKey {
+    serial: String,
+    location: String,
+    name: String,
+    index: usize,
+}
+
+impl ControllerKey {
+    fn new(controller: &ControllerData, controllers: &[ControllerData]) -> Self {
+        let mut key = Self {
+            serial: controller.metadata.serial.clone(),
+            location: controller.metadata.location.clone(),
+            name: controller.name.clone(),
+            index: 0,
+        };
+        key.index = controllers
+            .iter()
+            .take_while(|c| c.id != controller.id)
+            .filter(|c| key.is_same(c))
+            .count();
+        key
+    }
+
+    fn is_same(&self, controller: &ControllerData) -> bool {
+        controller.metadata.serial == self.serial
+            && controller.metadata.location == self.location
+            && controller.name == self.name
+    }
+
+    /// The current id of the controller
+    fn find(&self, controllers: &[ControllerData]) -> Option<u32> {
+        controllers
+            .iter()
+            .filter(|c| self.is_same(c))
+            .nth(self.index)
+            .map(|c| c.id)
+    }
+}
+
+impl fmt::Display for ControllerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.name, self.location)?;
+        if !self.serial.is_empty() {
+            write!(f, " (serial {})", self.serial)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sets colors of OpenRGB controllers, matching the selector
+pub struct OpenRGBOutput {
+    client: ClientHandle,
+    selector: Selector,
+    leds: Vec<LedSelector>,
+    direct_mode: bool,
+    modes_switched: bool,
+    saved_modes: Vec<(ControllerKey, i32)>,
+}
+
+impl OpenRGBOutput {
+    pub fn new(client: ClientHandle, selector: Selector) -> Self {
+        Self {
+            client,
+            selector,
+            leds: Vec::new(),
+            direct_mode: false,
+            modes_switched: false,
+            saved_modes: Vec::new(),
+        }
+    }
+
+    /// Switches the controllers to Direct/Static mode before setting the color and restores the
+    /// original modes on close
+    pub fn set_direct_mode(&mut self, direct_mode: bool) {
+        self.direct_mode = direct_mode;
+    }
+
+    /// Sets colors of the LEDs, matching the selectors, only, the rest of LEDs are left untouched
+    pub fn set_leds(&mut self, leds: Vec<LedSelector>) {
+        self.leds = leds;
+    }
+
+    pub fn client(&self) -> &ClientHandle {
+        &self.client
+    }
+
+    fn set_custom_modes(&mut self) {
+        let selector = self.selector.clone();
+        match self
+            .client
+            .call(move |client| Ok(set_custom_modes(client, &selector)))
+        {
+            // after reloads the controllers are already in the custom mode, keep the original
+            // modes and save the modes of new controllers only
+            Ok(modes) => {
+                self.modes_switched = true;
+                for (key, mode_id) in modes {
+                    if !self.saved_modes.iter().any(|(k, _)| *k == key) {
+                        self.saved_modes.push((key, mode_id));
+                    }
+                }
+            }
+            Err(e) => error!("Unable to set custom modes: {}", e),
+        }
+    }
+
+    fn restore_modes(&self) {
+        let saved_modes = self.saved_modes.clone();
+        // ids may change after reloads, so they are resolved right before restoring
+        let _ = self
+            .client
+            .call(move |client| {
+                for (key, mode_id) in saved_modes {
+                    if mode_id < 0 {
+                        continue;
+                    }
+                    match key.find(&client.controllers) {
+                        Some(id) => {
+                            debug!("Restoring controller {} mode {}", id, mode_id);
+                            let _ = client.set_mode(id, mode_id as u32).map_err(|e| {
+                                error!("Unable to restore mode for controller {}: {}", key, e)
+                            });
+                        }
+                        None => debug!("Controller {} is gone, mode not restored", key),
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|e| error!("Unable to restore modes: {}", e));
+    }
+}
+
+/// Switches the controllers, matching the selector, to the custom mode, returns their previous modes
+fn set_custom_modes(client: &mut OpenRGBClient, selector: &Selector) -> Vec<(ControllerKey, i32)> {
+    let to_switch: Vec<(u32, ControllerKey, i32)> = selector
+        .select(&client.controllers)
+        .iter()
+        .map(|c| {
+            (
+                c.id,
+                ControllerKey::new(c, &client.controllers),
+                c.active_mode,
+            )
+        })
+        .collect();
+    let mut saved = Vec::new();
+    for (id, key, mode_id) in to_switch {
+        debug!("Switching controller {} to custom mode", id);
+        match client.set_custom_mode(id) {
+            Ok(_) => saved.push((key, mode_id)),
+            Err(e) => error!("Unable to set custom mode for controller {}: {}", id, e),
+        }
+    }
+    saved
+}
+
+impl Output for OpenRGBOutput {
+    fn set_color(&mut self, color: &RGBColor) -> Result<(), Error> {
+        if self.direct_mode && !self.modes_switched {
+            self.set_custom_modes();
+        }
+        if self.leds.is_empty() {
+            self.client.set_color_by_selector(&self.selector, color)
+        } else {
+            self.client.set_leds_color(&self.leds, color)
+        }
+    }
+
+    /// The client itself is reloaded by the monitor, once for all outputs
+    fn reload(&mut self) -> Result<(), Error> {
+        if self.direct_mode {
+            self.set_custom_modes();
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        self.restore_modes();
+        Ok(())
+    }
+}
+
+/// A color map and the output it drives
+struct Channel {
+    color_map: Box<dyn ColorMap>,
+    output: Box<dyn Output>,
+    color: Option<RGBColor>,
+    /// Set on errors, unforced colors are not set until the next load sample
+    failed: bool,
+}
+
+impl Channel {
+    fn apply(&mut self, load: u8, time: Duration, force: bool) {
+        if self.failed && !force {
+            return;
+        }
+        let color = self.color_map.color_at_time(load, time);
+        if force || Some(color) != self.color {
+            debug!("Setting color: {}", color.colorize_self());
+            match self.output.set_color(&color) {
+                Ok(_) => {
+                    self.color = Some(color);
+                    self.failed = false;
+                }
+                Err(e) => {
+                    error!("Unable to set color: {}", e);
+                    self.failed = true;
+                }
+            }
+        }
+    }
+}
+
+pub struct Monitor {
+    channels: Vec<Channel>,
+    client: Option<ClientHandle>,
+    suspend_profile: Option<String>,
+    exit_profile: Option<String>,
+    load: Option<u8>,
+    load_diff: u8,
+    active: bool,
+    started: Instant,
+}
+
+impl Monitor {
+    pub fn new<C, O>(color_map: C, output: O) -> Self
+    where
+        C: ColorMap + 'static,
+        O: Output + 'static,
+    {
+        let mut monitor = Self {
+            channels: Vec::new(),
+            client: None,
+            suspend_profile: None,
+            exit_profile: None,
+            load: None,
+            load_diff: 1,
+            active: true,
+            started: Instant::now(),
+        };
+        monitor.add_channel(color_map, output);
+        monitor
+    }
+
+    /// Adds one more output with its own color map (e.g. a different gradient for some devices),
+    /// all channels follow the same load
+    pub fn add_channel<C, O>(&mut self, color_map: C, output: O)
+    where
+        C: ColorMap + 'static,
+        O: Output + 'static,
+    {
+        self.channels.push(Channel {
+            color_map: Box::new(color_map),
+            output: Box::new(output),
+            color: None,
+            failed: false,
+        });
+    }
+
+    /// Sets the OpenRGB client, shared by the outputs. It is reloaded and polled for notifications
+    /// once for all channels
+    pub fn set_client(&mut self, client: ClientHandle) {
+        self.client = Some(client);
+    }
+
+    /// Loads the profile on suspend instead of turning LEDs off, requires the client
+    pub fn set_suspend_profile(&mut self, profile: Option<String>) {
+        self.suspend_profile = profile;
+    }
+
+    /// Loads the profile on close, after the outputs are closed, requires the client
+    pub fn set_exit_profile(&mut self, profile: Option<String>) {
+        self.exit_profile = profile;
+    }
+
+    /// The minimal load change to update the color, lower values are treated as zero
+    pub fn set_load_diff(&mut self, load_diff: u8) {
+        self.load_diff = load_diff;
+    }
+
+    /// The current load, None if not received yet
+    pub fn load(&self) -> Option<u8> {
+        self.load
+    }
+
+    /// The color, applied last to the first channel
+    pub fn color(&self) -> Option<RGBColor> {
+        self.channels[0].color
+    }
+
+    /// The colors, applied last, for all channels
+    pub fn colors(&self) -> Vec<Option<RGBColor>> {
+        self.channels.iter().map(|c| c.color).collect()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// True if any channel has animated colors
+    pub fn is_animated(&self) -> bool {
+        self.channels.iter().any(|c| c.color_map.is_animated())
+    }
+
+    /// Suspends the monitor
+    pub fn stop(&mut self) {
+        self.active = false;
+        debug!("Suspending");
+        if let (Some(client), Some(profile)) = (&self.client, &self.suspend_profile) {
+            debug!("Loading profile {}", profile);
+            let _ = client
+                .load_profile(profile)
+                .map_err(|e| error!("Unable to suspend: {}", e));
+            return;
+        }
+        for channel in &mut self.channels {
+            let _ = channel
+                .output
+                .suspend()
+                .map_err(|e| error!("Unable to suspend: {}", e));
+        }
+    }
+
+    /// Resumes the monitor and forcibly applies the current color
+    pub fn start(&mut self) {
+        debug!("Resuming");
+        self.active = true;
+        self.apply(true);
+    }
+
+    /// Reloads the client and the output devices
+    pub fn reload(&mut self) {
+        if let Some(ref client) = self.client {
+            if let Err(e) = client.reload() {
+                error!("Server connection error: {}", e);
+                return;
+            }
+        }
+        for channel in &mut self.channels {
+            let _ = channel
+                .output
+                .reload()
+                .map_err(|e| error!("Server connection error: {}", e));
+        }
+    }
+
+    /// Checks the client and the outputs for external changes (e.g. hot-plugged devices) and
+    /// applies the color again if required
+    pub fn refresh(&mut self) {
+        let mut changed = false;
+        match self.client.as_ref().map(|c| c.poll_notifications()) {
+            Some(Ok(notifications)) if notifications.contains(&Notification::DeviceListUpdated) => {
+                info!("Device list updated, reloading data");
+                self.reload();
+                changed = true;
+            }
+            Some(Err(e)) => error!("Unable to refresh: {}", e),
+            _ => {}
+        }
+        for channel in &mut self.channels {
+            match channel.output.refresh() {
+                Ok(v) => changed |= v,
+                Err(e) => error!("Unable to refresh: {}", e),
+            }
+        }
+        if changed {
+            self.apply(true);
+        }
+    }
+
+    /// Prepares the outputs for exit
+    pub fn close(&mut self) {
+        for channel in &mut self.channels {
+            let _ = channel
+                .output
+                .close()
+                .map_err(|e| error!("Unable to close: {}", e));
+        }
+        if let (Some(client), Some(profile)) = (&self.client, &self.exit_profile) {
+            debug!("Loading profile {}", profile);
+            let _ = client
+                .load_profile(profile)
+                .map_err(|e| error!("Unable to close: {}", e));
+        }
+    }
+
+    /// Applies the color for the current load. If not forced, the color is set only if changed
+    pub fn apply(&mut self, force: bool) {
+        if let (true, Some(load)) = (self.active, self.load) {
+            let time = self.started.elapsed();
+            for channel in &mut self.channels {
+                channel.apply(load, time, force);
+            }
+        }
+    }
+
+    /// Sets the load and applies the color
+    pub fn set_load(&mut self, load: u8) {
+        self.load = Some(load);
+        self.apply(false);
+    }
+
+    /// Sets the sampled load, if it differs from the current one at least by load diff. Channels,
+    /// failed to set the color, are retried
+    pub fn update_load(&mut self, mut load: u8) {
+        for channel in &mut self.channels {
+            channel.failed = false;
+        }
+        if load < self.load_diff {
+            load = 0;
+        }
+        match self.load {
+            Some(prev) if (prev as i16 - load as i16).unsigned_abs() < self.load_diff as u16 => {
+                self.apply(false)
+            }
+            _ => self.set_load(load),
+        }
+    }
+}
+
+/// Runs the monitor: samples the metric every interval and updates the load, animated colors are
+/// updated every animation step. The monitor is not locked while sampling, so it can be
+/// controlled from other threads. Returns on metric errors
+pub fn run<M: Metric>(monitor: &Mutex<Monitor>, mut metric: M, interval: Duration) -> Error {
+    let finished = AtomicBool::new(false);
+    let animated = monitor.lock().unwrap().is_animated();
+    thread::scope(|s| {
+        if animated {
+            s.spawn(|| {
+                while !finished.load(Ordering::SeqCst) {
+                    thread::sleep(ANIMATION_STEP);
+                    monitor.lock().unwrap().apply(false);
+                }
+            });
+        }
+        let e = loop {
+            let load = match metric.sample(interval) {
+                Ok(v) => v,
+                Err(e) => break e,
+            };
+            let mut monitor = monitor.lock().unwrap();
+            monitor.refresh();
+            monitor.update_load(load);
+        };
+        finished.store(true, Ordering::SeqCst);
+        e
+    })
+}
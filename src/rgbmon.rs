@@ -1,12 +1,14 @@
-use rgbmon::{OpenRGBClient, RGBColor, VERSION};
-
-#[macro_use]
-extern crate lazy_static;
+use rgbmon::band::{Band, Fill};
+use rgbmon::capture::ReplayServer;
+use rgbmon::gradient::Gradient;
+use rgbmon::handle::ClientHandle;
+use rgbmon::monitor::{self, CpuLoad, GradientColorMap, Monitor, OpenRGBOutput};
+use rgbmon::selector::{select_leds, LedSelector, Selector};
+use rgbmon::{DeviceType, Error, OpenRGBClient, VERSION};
 
 use chrono::prelude::*;
 use clap::Clap;
 use colored::Colorize;
-use cpu_monitor::CpuInstant;
 use daemonize::Daemonize;
 use signal_hook::{
     consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1},
@@ -14,18 +16,14 @@ use signal_hook::{
 };
 use std::io::Write;
 use std::process;
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
 use log::LevelFilter;
-use log::{debug, info, warn, error, Level, Metadata, Record};
+use log::{debug, error, info, warn, Level, Metadata, Record};
 use syslog::{BasicLogger, Facility, Formatter3164};
 
-const COLORS: u32 = 0xFFFFFF;
-const START: u32 = 4340064;
-const END: u32 = 0xFFFFFF;
-
 #[derive(Clap)]
 #[clap(version = VERSION, about = "https://github.com/divi255/rgbmon")]
 struct Opts {
@@ -49,9 +47,16 @@ struct Opts {
     load_diff: u8,
     #[clap(
         long = "default-color",
-        about = "Default color for low CPU load (N:RRGGBB)"
+        about = "Default color for low CPU load (N:COLOR, e.g. 20:99CCFF, 20:#9CF, 20:navy)"
     )]
-    default_color: Option<String>,
+    default_color: Option<DefaultColor>,
+    #[clap(
+        long = "band",
+        about = "Load band, FROM-TO:FILL, the fill is a color, gradient stops or flash(COLOR[,SECS]) / breathe(COLOR[,SECS]) (e.g. 85-100:flash(red)), can be repeated, the first matching band is used",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    bands: Vec<Band>,
     #[clap(
         long = "pid-file",
         about = "Pid file location",
@@ -60,92 +65,105 @@ struct Opts {
     pid_file: String,
     #[clap(
         long = "connect",
-        about = "OpenRGB server host:port to connect to",
+        about = "OpenRGB server host:port or unix:/path/to/socket to connect to",
         default_value = "127.0.0.1:6742"
     )]
     connect: String,
     #[clap(
         long = "device-types",
-        about = "Device types to operate, comma separated",
-        default_value = "0,1,2,3,4",
+        about = "Device types to operate, comma separated (names or numbers)",
+        default_value = "motherboard,dram,gpu,cooler,ledstrip",
         multiple = true,
         value_delimiter = ","
     )]
-    device_types: Vec<u32>,
+    device_types: Vec<DeviceType>,
+    #[clap(
+        long = "devices",
+        about = "Controllers to operate, overrides device types (e.g. vendor=ASUS&type=dram;name=*Strip*)"
+    )]
+    devices: Option<Selector>,
+    #[clap(
+        long = "leds",
+        about = "Operate only LEDs, matching the rule, of the selected devices or device types, can be repeated (e.g. type=keyboard&led=Key: F*)",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    leds: Vec<LedSelector>,
+    #[clap(
+        long = "gradient",
+        about = "CPU load colors, [SELECTOR@]STOPS (e.g. 0:0000FF,50:00FF00,100:FF0000,oklab), can be repeated for different devices",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    gradients: Vec<DeviceGradient>,
+    #[clap(
+        long = "direct-mode",
+        about = "Switch managed controllers to Direct/Static mode, restore on exit"
+    )]
+    direct_mode: bool,
+    #[clap(
+        long = "suspend-profile",
+        about = "OpenRGB profile to load on suspend instead of turning LEDs off"
+    )]
+    suspend_profile: Option<String>,
+    #[clap(long = "exit-profile", about = "OpenRGB profile to load on exit")]
+    exit_profile: Option<String>,
+    #[clap(
+        long = "capture",
+        about = "Write all OpenRGB protocol packets to the file, for bug reports"
+    )]
+    capture: Option<String>,
+    #[clap(
+        long = "replay",
+        about = "Replay a captured session instead of connecting to the server",
+        // the replay server thread would not survive fork
+        conflicts_with = "daemonize"
+    )]
+    replay: Option<String>,
 }
 
-struct State {
-    load: u8,
-    color: RGBColor,
-    min_load: Option<u8>,
-    default_color: Option<RGBColor>,
-    active: bool,
-    device_types: Vec<u32>,
-}
+/// Load threshold and color, N:COLOR, the same as the band 0-N:COLOR
+struct DefaultColor(Band);
 
-impl State {
-    fn new() -> Self {
-        Self {
-            load: std::u8::MAX,
-            color: RGBColor::new(0, 0, 0),
-            min_load: None,
-            default_color: None,
-            active: true,
-            device_types: Vec::new(),
-        }
-    }
+impl std::str::FromStr for DefaultColor {
+    type Err = Error;
 
-    fn stop(&mut self) {
-        self.active = false;
-        debug!("Suspending");
-        let _ = ORGB
-            .write()
-            .unwrap()
-            .set_color_by_device_types(&self.device_types, &RGBColor::black())
-            .map_err(|e| error!("Unable to set color: {}", e));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sp = s.splitn(2, ':');
+        let load = sp.next().unwrap();
+        let color = sp
+            .next()
+            .ok_or_else(|| Error::InvalidValue(format!("no color specified: {}", s)))?;
+        let load = load
+            .parse()
+            .map_err(|e| Error::InvalidValue(format!("load {:?}: {}", load, e)))?;
+        Band::new(0, load, Fill::Solid(color.parse()?)).map(DefaultColor)
     }
+}
 
-    fn start(&mut self) {
-        debug!("Resuming");
-        self.active = true;
-        self.apply(true);
-    }
+/// [SELECTOR@]GRADIENT
+struct DeviceGradient {
+    selector: Option<Selector>,
+    gradient: Gradient,
+}
 
-    fn apply(&mut self, force: bool) {
-        if self.active && self.load != std::u8::MAX {
-            let color;
-            if self.min_load.is_some() && self.load as u8 <= self.min_load.unwrap() {
-                color = self.default_color.unwrap().clone();
-            } else {
-                color = RGBColor::rainbow(self.load as u32, COLORS, START, END);
-            }
-            if force || color != self.color {
-                debug!("Setting color: {}", color.colorize_self());
-                match ORGB
-                    .write()
-                    .unwrap()
-                    .set_color_by_device_types(&self.device_types, &color)
-                {
-                    Ok(_) => self.color = color,
-                    Err(e) => {
-                        error!("Unable to set color: {}", e);
-                    }
-                }
-            }
-        }
-    }
+impl std::str::FromStr for DeviceGradient {
+    type Err = Error;
 
-    fn set_load(&mut self, load: u8) {
-        self.load = load;
-        self.apply(false);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once('@') {
+            Some((selector, gradient)) => Self {
+                selector: Some(selector.parse()?),
+                gradient: gradient.parse()?,
+            },
+            None => Self {
+                selector: None,
+                gradient: s.parse()?,
+            },
+        })
     }
 }
 
-lazy_static! {
-    static ref STATE: RwLock<State> = RwLock::new(State::new());
-    static ref ORGB: RwLock<OpenRGBClient> = RwLock::new(OpenRGBClient::new());
-}
-
 struct SimpleLogger;
 
 impl log::Log for SimpleLogger {
@@ -186,7 +204,7 @@ fn set_verbose_logger(filter: LevelFilter) {
 fn main() {
     #[cfg(windows)]
     colored::control::set_override(false);
-    let mut opts: Opts = Opts::parse();
+    let opts: Opts = Opts::parse();
     if opts.verbose {
         set_verbose_logger(LevelFilter::Debug);
     } else if std::env::var("DISABLE_SYSLOG").unwrap_or("0".to_owned()) == "1" {
@@ -209,42 +227,108 @@ fn main() {
             }
         }
     }
-    let device_types = std::sync::Arc::new(&opts.device_types);
-    debug!(
-        "Device types managed: {}",
-        device_types
-            .clone()
-            .into_iter()
-            .map(|i| i.to_string() + " ")
-            .collect::<String>()
-    );
-    {
-        let mut client = ORGB.write().unwrap();
-        let mut state = STATE.write().unwrap();
-        client.set_path(&opts.connect);
-        state.device_types.append(&mut opts.device_types);
-        match client.load() {
-            Ok(_) => {
-                if client.controllers.is_empty() {
-                    warn!("no controllers connected");
-                } else {
-                    let mut found = false;
-                    for c in &client.controllers {
-                        if state.device_types.contains(&c.device_type) {
-                            found = true;
-                            break;
-                        }
-                    }
-                    if !found {
-                        warn!("no device types to control");
-                    }
-                }
+    let device_types = opts.device_types;
+    let devices = opts
+        .devices
+        .unwrap_or_else(|| Selector::device_types(&device_types));
+    // LED rules are limited to the selected devices
+    let leds: Vec<LedSelector> = opts
+        .leds
+        .into_iter()
+        .map(|l| LedSelector {
+            controllers: match l.controllers {
+                Selector::Any => devices.clone(),
+                c => c.and(devices.clone()),
+            },
+            name: l.name,
+        })
+        .collect();
+    let selector = if leds.is_empty() {
+        devices
+    } else {
+        Selector::Or(leds.iter().map(|l| l.controllers.clone()).collect())
+    };
+    debug!("Controllers managed: {}", selector);
+    for l in &leds {
+        debug!("LEDs managed: {}", l);
+    }
+    let mut default_gradient = Gradient::rainbow();
+    let mut device_gradients = Vec::new();
+    for g in opts.gradients {
+        match g.selector {
+            Some(s) => device_gradients.push((s, g.gradient)),
+            None => default_gradient = g.gradient,
+        }
+    }
+    // controllers without own gradients use the default one
+    let rest = if device_gradients.is_empty() {
+        selector.clone()
+    } else {
+        selector.clone().and(!Selector::Or(
+            device_gradients.iter().map(|(s, _)| s.clone()).collect(),
+        ))
+    };
+    let mut channels = vec![(rest, default_gradient)];
+    for (s, g) in device_gradients {
+        channels.push((selector.clone().and(s), g));
+    }
+    // the replay server must live until the process exits
+    let replay_server = opts.replay.as_ref().map(|path| {
+        ReplayServer::open(path)
+            .map_err(|e| {
+                println!(
+                    "{}",
+                    format!("Unable to replay capture {}: {}", path, e).red()
+                )
+            })
+            .unwrap()
+    });
+    let mut client = OpenRGBClient::new();
+    match replay_server {
+        Some(ref server) => client.set_path(server.addr()),
+        None => client.set_path(&opts.connect),
+    }
+    if let Some(ref path) = opts.capture {
+        client
+            .set_capture(path)
+            .map_err(|e| {
+                println!(
+                    "{}",
+                    format!("Unable to create capture file {}: {}", path, e).red()
+                )
+            })
+            .unwrap();
+    }
+    match client.load() {
+        Ok(_) => {
+            if client.controllers.is_empty() {
+                warn!("no controllers connected");
+            } else if selector.select(&client.controllers).is_empty() {
+                warn!("no controllers to operate");
+            } else if !leds.is_empty() && select_leds(&leds, &client.controllers).is_empty() {
+                warn!("no LEDs to operate");
+            }
+            if channels.len() > 1
+                && !client.controllers.is_empty()
+                && channels[0].0.select(&client.controllers).is_empty()
+            {
+                // all controllers have own gradients
+                channels.remove(0);
             }
-            Err(e) => error!("Server connection error: {}", e),
         }
+        Err(e) => error!("Server connection error: {}", e),
+    }
+    let mut bands = Vec::new();
+    if let Some(DefaultColor(band)) = opts.default_color {
+        debug!("Default color for load <= {}: {}", band.to(), band.fill());
+        bands.push(band);
+    }
+    for band in opts.bands {
+        debug!("Load band: {}", band);
+        bands.push(band);
     }
     let sleep_step: Duration = Duration::from_millis((opts.sleep_step * 1000.) as u64);
-    let mut signals = Signals::new(&[SIGHUP, SIGUSR1, SIGINT, SIGTERM]).unwrap();
+    let mut signals = Signals::new([SIGHUP, SIGUSR1, SIGINT, SIGTERM]).unwrap();
     let pid_file = opts.pid_file;
     debug!("Writing pid file: {}", pid_file);
     if opts.daemonize {
@@ -261,17 +345,48 @@ fn main() {
             .write_all(format!("{}", process::id()).as_bytes())
             .unwrap();
     }
+    // threads do not survive fork, so the client worker is started after daemonizing
+    let client = ClientHandle::new(client);
+    let mut monitor: Option<Monitor> = None;
+    for (selector, gradient) in channels {
+        debug!("Gradient for {}: {}", selector, gradient);
+        let mut color_map = GradientColorMap::new(gradient);
+        color_map.bands = bands.clone();
+        let mut output = OpenRGBOutput::new(client.clone(), selector.clone());
+        output.set_leds(
+            leds.iter()
+                .map(|l| LedSelector {
+                    controllers: l.controllers.clone().and(selector.clone()),
+                    name: l.name.clone(),
+                })
+                .collect(),
+        );
+        output.set_direct_mode(opts.direct_mode);
+        match monitor {
+            Some(ref mut m) => m.add_channel(color_map, output),
+            None => monitor = Some(Monitor::new(color_map, output)),
+        }
+    }
+    let mut monitor = monitor.unwrap();
+    monitor.set_client(client);
+    monitor.set_suspend_profile(opts.suspend_profile);
+    monitor.set_exit_profile(opts.exit_profile);
+    monitor.set_load_diff(opts.load_diff);
+    let monitor = Arc::new(Mutex::new(monitor));
+    let signal_monitor = monitor.clone();
     thread::spawn(move || {
         for sig in signals.forever() {
             debug!("Received signal {:?}", sig);
             match sig {
                 SIGHUP => {
                     info!("Reloading data");
-                    let _ = ORGB.write().unwrap().reload();
-                    STATE.write().unwrap().start();
+                    let mut monitor = signal_monitor.lock().unwrap();
+                    monitor.reload();
+                    monitor.start();
                 }
-                SIGUSR1 => STATE.write().unwrap().stop(),
+                SIGUSR1 => signal_monitor.lock().unwrap().stop(),
                 SIGTERM | SIGINT => {
+                    signal_monitor.lock().unwrap().close();
                     let _ = std::fs::remove_file(pid_file);
                     process::exit(0);
                 }
@@ -279,36 +394,8 @@ fn main() {
             }
         }
     });
-    match opts.default_color {
-        Some(s) => {
-            let mut state = STATE.write().unwrap();
-            let v: Vec<&str> = s.split(':').collect();
-            state.min_load = Some(v[0].parse().unwrap());
-            let c = RGBColor::from_str(v[1]);
-            debug!(
-                "Default color for load < {}: {}",
-                state.min_load.unwrap(),
-                c.colorize_self(),
-            );
-            state.default_color = Some(c);
-        }
-        None => {}
-    }
     info!("started");
-    loop {
-        let start = CpuInstant::now().unwrap();
-        thread::sleep(sleep_step);
-        let end = CpuInstant::now().unwrap();
-        let mut load = ((end - start).non_idle() * 100.) as u8;
-        debug!("CPU load: {}", format!("{}%", &load).cyan());
-        if load < opts.load_diff {
-            load = 0;
-        }
-        let prev_load = STATE.read().unwrap().load;
-        if prev_load == std::u8::MAX
-            || (prev_load as i16 - load as i16).abs() as u8 >= opts.load_diff
-        {
-            STATE.write().unwrap().set_load(load);
-        }
-    }
+    let e = monitor::run(&monitor, CpuLoad::new(), sleep_step);
+    error!("Unable to get CPU load: {}", e);
+    process::exit(1);
 }
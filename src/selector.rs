@@ -0,0 +1,425 @@
+//! Controller selectors
+//!
+//! Controller ids depend on the enumeration order, so stable properties (serial, location,
+//! vendor, name patterns) can be used to select controllers instead.
+//!
+//! The text form, e.g. for the command line, is a list of alternatives separated by ";", each
+//! alternative is a list of conditions separated by "&":
+//!
+//! ```text
+//! vendor=ASUS&type=dram;name=*Strix*;serial=0123456
+//! ```
+//!
+//! Conditions: id=N, type=device_type, name=glob, name~=regex, serial=S, location=S, vendor=S,
+//! "!" before a condition negates it
+//!
+//! LED selectors use the same conditions for controllers, plus a single LED name condition
+//! (led=glob or led~=regex), separated by "&", e.g. "type=keyboard&led=Key: F*". Alternatives
+//! share the LED name condition, e.g. "type=keyboard;type=mouse&led=Logo"
+use crate::{ControllerData, DeviceType, Error, LedData};
+use regex::Regex;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum Selector {
+    /// Matches all controllers
+    Any,
+    Id(u32),
+    /// The exact name
+    Name(String),
+    /// The name regular expression (globs are converted to regular expressions)
+    NameRegex(Regex),
+    DeviceType(DeviceType),
+    Serial(String),
+    Location(String),
+    Vendor(String),
+    /// All of the selectors must match
+    And(Vec<Selector>),
+    /// Any of the selectors must match
+    Or(Vec<Selector>),
+    /// The selector must not match
+    Not(Box<Selector>),
+}
+
+impl Selector {
+    pub fn name_regex(pattern: &str) -> Result<Self, Error> {
+        Regex::new(pattern)
+            .map(Selector::NameRegex)
+            .map_err(|e| Error::InvalidValue(format!("name regex {}: {}", pattern, e)))
+    }
+
+    /// Name glob pattern: "*" matches any number of characters, "?" matches a single one,
+    /// "[...]" matches a character class ("[!...]" - negated)
+    pub fn name_glob(pattern: &str) -> Result<Self, Error> {
+        Self::name_regex(&glob_to_regex(pattern))
+    }
+
+    /// Matches any of the device types
+    pub fn device_types(device_types: &[DeviceType]) -> Self {
+        Selector::Or(
+            device_types
+                .iter()
+                .map(|d| Selector::DeviceType(*d))
+                .collect(),
+        )
+    }
+
+    pub fn and(self, other: Selector) -> Self {
+        match self {
+            Selector::And(mut v) => {
+                v.push(other);
+                Selector::And(v)
+            }
+            _ => Selector::And(vec![self, other]),
+        }
+    }
+
+    pub fn or(self, other: Selector) -> Self {
+        match self {
+            Selector::Or(mut v) => {
+                v.push(other);
+                Selector::Or(v)
+            }
+            _ => Selector::Or(vec![self, other]),
+        }
+    }
+
+    pub fn matches(&self, controller: &ControllerData) -> bool {
+        match self {
+            Selector::Any => true,
+            Selector::Id(id) => controller.id == *id,
+            Selector::Name(name) => controller.name == *name,
+            Selector::NameRegex(re) => re.is_match(&controller.name),
+            Selector::DeviceType(t) => controller.device_type == *t,
+            Selector::Serial(serial) => controller.metadata.serial == *serial,
+            Selector::Location(location) => controller.metadata.location == *location,
+            Selector::Vendor(vendor) => controller.metadata.vendor == *vendor,
+            Selector::And(v) => v.iter().all(|s| s.matches(controller)),
+            Selector::Or(v) => v.iter().any(|s| s.matches(controller)),
+            Selector::Not(s) => !s.matches(controller),
+        }
+    }
+
+    /// Single conditions, which are printed as is
+    fn is_condition(&self) -> bool {
+        !matches!(self, Selector::And(_) | Selector::Or(_) | Selector::Not(_))
+    }
+
+    /// The selector as alternatives of conditions, the way the text form groups them. Negations
+    /// are moved down to single conditions
+    fn alternatives(&self) -> Vec<Vec<Selector>> {
+        match self {
+            Selector::And(v) => v.iter().fold(vec![Vec::new()], |acc, s| {
+                let alternatives = s.alternatives();
+                acc.iter()
+                    .flat_map(|a| {
+                        alternatives.iter().map(move |b| {
+                            let mut conditions = a.clone();
+                            conditions.extend(b.iter().cloned());
+                            conditions
+                        })
+                    })
+                    .collect()
+            }),
+            Selector::Or(v) => v.iter().flat_map(Selector::alternatives).collect(),
+            Selector::Not(s) => match **s {
+                Selector::And(ref v) => {
+                    Selector::Or(v.iter().map(|s| !s.clone()).collect()).alternatives()
+                }
+                Selector::Or(ref v) => {
+                    Selector::And(v.iter().map(|s| !s.clone()).collect()).alternatives()
+                }
+                Selector::Not(ref s) => s.alternatives(),
+                _ => vec![vec![self.clone()]],
+            },
+            _ => vec![vec![self.clone()]],
+        }
+    }
+
+    /// Returns the matching controllers
+    pub fn select<'a>(&self, controllers: &'a [ControllerData]) -> Vec<&'a ControllerData> {
+        controllers.iter().filter(|c| self.matches(c)).collect()
+    }
+
+    fn parse_conditions(s: &str) -> Result<Self, Error> {
+        let mut conditions = s
+            .split('&')
+            .map(Self::parse_condition)
+            .collect::<Result<Vec<Selector>, Error>>()?;
+        Ok(if conditions.len() == 1 {
+            conditions.remove(0)
+        } else {
+            Selector::And(conditions)
+        })
+    }
+
+    fn parse_condition(s: &str) -> Result<Self, Error> {
+        if let Some(cond) = s.strip_prefix('!') {
+            return Ok(!Self::parse_condition(cond)?);
+        }
+        if s == "*" {
+            return Ok(Selector::Any);
+        }
+        if let Some(pattern) = s.strip_prefix("name~=") {
+            return Self::name_regex(pattern);
+        }
+        let mut sp = s.splitn(2, '=');
+        let key = sp.next().unwrap();
+        let value = sp
+            .next()
+            .ok_or_else(|| Error::InvalidValue(format!("invalid selector condition: {}", s)))?;
+        match key {
+            "id" => value
+                .parse()
+                .map(Selector::Id)
+                .map_err(|e| Error::InvalidValue(format!("controller id {}: {}", value, e))),
+            "type" => value.parse().map(Selector::DeviceType),
+            "name" => Self::name_glob(value),
+            "serial" => Ok(Selector::Serial(value.to_owned())),
+            "location" => Ok(Selector::Location(value.to_owned())),
+            "vendor" => Ok(Selector::Vendor(value.to_owned())),
+            _ => Err(Error::InvalidValue(format!(
+                "unknown selector condition: {}",
+                key
+            ))),
+        }
+    }
+}
+
+impl std::ops::Not for Selector {
+    type Output = Selector;
+
+    fn not(self) -> Self::Output {
+        match self {
+            Selector::Not(s) => *s,
+            _ => Selector::Not(Box::new(self)),
+        }
+    }
+}
+
+impl std::str::FromStr for Selector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut alternatives = Vec::new();
+        for alt in s.split(';').filter(|v| !v.is_empty()) {
+            alternatives.push(Self::parse_conditions(alt)?);
+        }
+        match alternatives.len() {
+            0 => Err(Error::InvalidValue("empty selector".to_owned())),
+            1 => Ok(alternatives.remove(0)),
+            _ => Ok(Selector::Or(alternatives)),
+        }
+    }
+}
+
+fn glob_to_regex(pattern: &str) -> String {
+    let mut re = "^".to_owned();
+    let mut class: Option<usize> = None;
+    for ch in pattern.chars() {
+        match (ch, class) {
+            ('*', None) => re.push_str(".*"),
+            ('?', None) => re.push('.'),
+            ('[', None) => {
+                class = Some(0);
+                re.push('[');
+            }
+            (']', Some(n)) if n > 0 => {
+                class = None;
+                re.push(']');
+            }
+            ('!', Some(0)) => {
+                class = Some(1);
+                re.push('^');
+            }
+            (_, Some(n)) => {
+                class = Some(n + 1);
+                match ch {
+                    '\\' | '[' | '&' | '~' => {
+                        re.push('\\');
+                        re.push(ch);
+                    }
+                    _ => re.push(ch),
+                }
+            }
+            (_, None) => re.push_str(&regex::escape(&ch.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, v: &[Selector], sep: &str) -> fmt::Result {
+    for (i, s) in v.iter().enumerate() {
+        if i > 0 {
+            write!(f, "{}", sep)?;
+        }
+        write!(f, "{}", s)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Selector::Any => write!(f, "*"),
+            Selector::Id(id) => write!(f, "id={}", id),
+            Selector::Name(name) => write!(f, "name={}", name),
+            Selector::NameRegex(re) => write!(f, "name~={}", re),
+            Selector::DeviceType(t) => write!(f, "type={}", t),
+            Selector::Serial(serial) => write!(f, "serial={}", serial),
+            Selector::Location(location) => write!(f, "location={}", location),
+            Selector::Vendor(vendor) => write!(f, "vendor={}", vendor),
+            Selector::Not(s) if s.is_condition() => write!(f, "!{}", s),
+            // the text form has no grouping, so nested selectors are expanded
+            Selector::And(_) | Selector::Or(_) | Selector::Not(_) => {
+                let alternatives = self.alternatives();
+                if alternatives.is_empty() {
+                    // matches nothing
+                    return write!(f, "!*");
+                }
+                for (i, conditions) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ";")?;
+                    }
+                    if conditions.is_empty() {
+                        write!(f, "*")?;
+                    } else {
+                        write_list(f, conditions, "&")?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Selects LEDs by name in the controllers, matching the controller selector
+#[derive(Debug, Clone)]
+pub struct LedSelector {
+    pub controllers: Selector,
+    pub name: Regex,
+}
+
+impl LedSelector {
+    /// LED name glob pattern, see [`Selector::name_glob`]
+    pub fn new(controllers: Selector, pattern: &str) -> Result<Self, Error> {
+        Self::with_regex(controllers, &glob_to_regex(pattern))
+    }
+
+    pub fn with_regex(controllers: Selector, pattern: &str) -> Result<Self, Error> {
+        Ok(Self {
+            controllers,
+            name: Regex::new(pattern)
+                .map_err(|e| Error::InvalidValue(format!("LED regex {}: {}", pattern, e)))?,
+        })
+    }
+
+    pub fn matches(&self, controller: &ControllerData, led: &LedData) -> bool {
+        self.controllers.matches(controller) && self.name.is_match(&led.name)
+    }
+
+    /// Returns ids of the matching LEDs, grouped by controller ids. Controllers without matching
+    /// LEDs are skipped
+    pub fn select(&self, controllers: &[ControllerData]) -> Vec<(u32, Vec<u32>)> {
+        select_leds(std::slice::from_ref(self), controllers)
+    }
+}
+
+/// Returns ids of the LEDs, matching any of the selectors, grouped by controller ids
+pub fn select_leds(
+    selectors: &[LedSelector],
+    controllers: &[ControllerData],
+) -> Vec<(u32, Vec<u32>)> {
+    let mut result = Vec::new();
+    for c in controllers {
+        let leds: Vec<u32> = c
+            .leds
+            .iter()
+            .enumerate()
+            .filter(|(_, led)| selectors.iter().any(|s| s.matches(c, led)))
+            .map(|(id, _)| id as u32)
+            .collect();
+        if !leds.is_empty() {
+            result.push((c.id, leds));
+        }
+    }
+    result
+}
+
+impl std::str::FromStr for LedSelector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut alternatives = Vec::new();
+        let mut name: Option<String> = None;
+        for alt in s.split(';').filter(|v| !v.is_empty()) {
+            let mut controllers = Vec::new();
+            let mut alt_name = None;
+            for cond in alt.split('&') {
+                let led = if let Some(pattern) = cond.strip_prefix("led~=") {
+                    Some(pattern.to_owned())
+                } else {
+                    cond.strip_prefix("led=").map(glob_to_regex)
+                };
+                match led {
+                    Some(_) if alt_name.is_some() => {
+                        return Err(Error::InvalidValue(format!(
+                            "multiple LED name conditions: {}",
+                            s
+                        )))
+                    }
+                    Some(pattern) => alt_name = Some(pattern),
+                    None => controllers.push(Selector::parse_conditions(cond)?),
+                }
+            }
+            // alternatives without the LED name condition share the one of the others
+            match (alt_name, &name) {
+                (Some(n), Some(prev)) if n != *prev => {
+                    return Err(Error::InvalidValue(format!(
+                        "different LED name conditions: {}",
+                        s
+                    )))
+                }
+                (Some(n), None) => name = Some(n),
+                _ => {}
+            }
+            alternatives.push(match controllers.len() {
+                0 => Selector::Any,
+                1 => controllers.remove(0),
+                _ => Selector::And(controllers),
+            });
+        }
+        let name =
+            name.ok_or_else(|| Error::InvalidValue(format!("no LED name condition: {}", s)))?;
+        let controllers = match alternatives.len() {
+            1 => alternatives.remove(0),
+            _ => Selector::Or(alternatives),
+        };
+        Self::with_regex(controllers, &name)
+    }
+}
+
+impl fmt::Display for LedSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // each alternative of the controller selector gets the LED name condition
+        let alternatives = match self.controllers {
+            Selector::Any => vec![Vec::new()],
+            ref c => c.alternatives(),
+        };
+        if alternatives.is_empty() {
+            // matches nothing
+            return write!(f, "!*&led~={}", self.name);
+        }
+        for (i, conditions) in alternatives.iter().enumerate() {
+            if i > 0 {
+                write!(f, ";")?;
+            }
+            for c in conditions {
+                write!(f, "{}&", c)?;
+            }
+            write!(f, "led~={}", self.name)?;
+        }
+        Ok(())
+    }
+}
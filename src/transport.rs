@@ -0,0 +1,308 @@
+//! Transports for the OpenRGB SDK protocol: TCP, Unix sockets and in-memory streams
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The default OpenRGB SDK server port
+pub const DEFAULT_PORT: u16 = 6742;
+
+/// Connection to the server
+pub trait Transport: Read + Write + Send + Sync {
+    /// Sets read and write timeouts
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+    /// In non-blocking mode reads return WouldBlock if no data is available
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()>;
+    /// Closes the connection in both directions
+    fn shutdown(&self) -> io::Result<()>;
+    /// Creates a new handle for the same connection, e.g. to read and write from different
+    /// threads
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+}
+
+/// Opens connections to the server. Called by the client for every (re)connect
+pub trait Connector: fmt::Display + Send + Sync {
+    fn connect(&self, timeout: Duration) -> io::Result<Box<dyn Transport>>;
+}
+
+impl Transport for TcpStream {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        self.set_write_timeout(Some(timeout))
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(TcpStream::try_clone(self)?))
+    }
+}
+
+/// TCP connector. The address is host:port, IPv4/IPv6 address or a host name, the default port
+/// is used if not specified
+pub struct TcpConnector {
+    addr: String,
+}
+
+impl TcpConnector {
+    pub fn new(addr: &str) -> Self {
+        Self {
+            addr: addr.to_owned(),
+        }
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+}
+
+/// Appends the default port to the address if not specified: host, IPv4 or IPv6 address
+pub(crate) fn with_default_port(addr: &str) -> String {
+    if addr.parse::<SocketAddr>().is_ok() {
+        return addr.to_owned();
+    }
+    let host = addr
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(addr);
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        SocketAddr::new(ip, DEFAULT_PORT).to_string()
+    } else if addr.contains(':') {
+        addr.to_owned()
+    } else {
+        format!("{}:{}", addr, DEFAULT_PORT)
+    }
+}
+
+impl fmt::Display for TcpConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr)
+    }
+}
+
+impl Connector for TcpConnector {
+    fn connect(&self, timeout: Duration) -> io::Result<Box<dyn Transport>> {
+        let mut err = None;
+        // try all resolved addresses, e.g. both IPv6 and IPv4 ones for localhost
+        for addr in with_default_port(&self.addr).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(v) => return Ok(Box::new(v)),
+                Err(e) => err = Some(e),
+            }
+        }
+        Err(err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unable to resolve {}", self.addr),
+            )
+        }))
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))?;
+        self.set_write_timeout(Some(timeout))
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(UnixStream::try_clone(self)?))
+    }
+}
+
+/// Unix socket connector, e.g. for a server, forwarded with ssh or socat
+#[cfg(unix)]
+pub struct UnixConnector {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl fmt::Display for UnixConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unix:{}", self.path.display())
+    }
+}
+
+#[cfg(unix)]
+impl Connector for UnixConnector {
+    fn connect(&self, _timeout: Duration) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(UnixStream::connect(&self.path)?))
+    }
+}
+
+/// Builds a connector for the path: "unix:/path/to/socket" for Unix sockets, TCP address
+/// otherwise
+pub fn connector(path: &str) -> Box<dyn Connector> {
+    #[cfg(unix)]
+    if let Some(socket) = path.strip_prefix("unix:") {
+        return Box::new(UnixConnector::new(socket));
+    }
+    Box::new(TcpConnector::new(path))
+}
+
+#[derive(Default)]
+struct Pipe {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+type SharedPipe = Arc<(Mutex<Pipe>, Condvar)>;
+
+fn close(pipe: &SharedPipe) {
+    let (lock, cvar) = &**pipe;
+    lock.lock().unwrap().closed = true;
+    cvar.notify_all();
+}
+
+struct Endpoint {
+    rx: SharedPipe,
+    tx: SharedPipe,
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        close(&self.rx);
+        close(&self.tx);
+    }
+}
+
+/// One end of an in-memory duplex stream. The connection is closed when all clones of the end
+/// are dropped
+pub struct MemoryStream {
+    endpoint: Arc<Endpoint>,
+    timeout: Option<Duration>,
+    nonblocking: bool,
+}
+
+/// Creates a pair of connected in-memory streams
+pub fn duplex() -> (MemoryStream, MemoryStream) {
+    let a: SharedPipe = Arc::default();
+    let b: SharedPipe = Arc::default();
+    let end = |rx: &SharedPipe, tx: &SharedPipe| MemoryStream {
+        endpoint: Arc::new(Endpoint {
+            rx: rx.clone(),
+            tx: tx.clone(),
+        }),
+        timeout: None,
+        nonblocking: false,
+    };
+    (end(&a, &b), end(&b, &a))
+}
+
+impl MemoryStream {
+    /// Creates a new handle for the same end of the stream
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            endpoint: self.endpoint.clone(),
+            timeout: self.timeout,
+            nonblocking: self.nonblocking,
+        })
+    }
+}
+
+impl Read for MemoryStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let (lock, cvar) = &*self.endpoint.rx;
+        let deadline = self.timeout.map(|v| Instant::now() + v);
+        let mut pipe = lock.lock().unwrap();
+        loop {
+            if !pipe.data.is_empty() {
+                let len = std::cmp::min(buf.len(), pipe.data.len());
+                for (dst, src) in buf.iter_mut().zip(pipe.data.drain(..len)) {
+                    *dst = src;
+                }
+                return Ok(len);
+            }
+            if pipe.closed {
+                return Ok(0);
+            }
+            if self.nonblocking {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            pipe = match deadline {
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    cvar.wait_timeout(pipe, d - now).unwrap().0
+                }
+                None => cvar.wait(pipe).unwrap(),
+            };
+        }
+    }
+}
+
+impl Write for MemoryStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (lock, cvar) = &*self.endpoint.tx;
+        let mut pipe = lock.lock().unwrap();
+        if pipe.closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        pipe.data.extend(buf);
+        cvar.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryStream {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = Some(timeout);
+        Ok(())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        close(&self.endpoint.rx);
+        close(&self.endpoint.tx);
+        Ok(())
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(MemoryStream::try_clone(self)?))
+    }
+}
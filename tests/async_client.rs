@@ -0,0 +1,297 @@
+mod common;
+
+use common::{mock_server, wait_for_async};
+use rgbmon::async_client::{AsyncOpenRGBClient, BlockingConnector};
+use rgbmon::capture::{read_capture, Direction};
+use rgbmon::mock::{controller, MockServer};
+use rgbmon::{
+    encode_packet, DeviceType, Error, Notification, PacketHeader, RGBColor, Request, Response,
+    PROTOCOL_VERSION,
+};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+async fn setup() -> (MockServer, AsyncOpenRGBClient) {
+    let server = mock_server();
+    let client = AsyncOpenRGBClient::new(server.addr());
+    client.load().await.unwrap();
+    (server, client)
+}
+
+#[tokio::test]
+async fn load() {
+    let (server, client) = setup().await;
+    assert_eq!(client.server_protocol(), Some(PROTOCOL_VERSION));
+    assert_eq!(client.protocol(), Some(PROTOCOL_VERSION));
+    assert_eq!(client.controllers(), server.controllers());
+}
+
+#[tokio::test]
+async fn load_protocol_0() {
+    let server = mock_server();
+    server.set_protocol(0);
+    let client = AsyncOpenRGBClient::new(server.addr());
+    client.set_timeout(Duration::from_millis(200));
+    let started = Instant::now();
+    client.load().await.unwrap();
+    // the version probe is not retried
+    assert!(started.elapsed() < Duration::from_millis(600));
+    assert_eq!(client.protocol(), Some(0));
+    assert_eq!(client.controllers().len(), 4);
+    assert!(matches!(
+        client.profiles().await,
+        Err(Error::UnsupportedVersion {
+            required: 2,
+            negotiated: 0
+        })
+    ));
+}
+
+#[tokio::test]
+async fn set_color_selectors() {
+    let (server, client) = setup().await;
+    let color = RGBColor::new(1, 2, 3);
+    client.set_color_by_name("Stick", &color).await.unwrap();
+    client
+        .set_color_by_device_type(DeviceType::Keyboard, &color)
+        .await
+        .unwrap();
+    wait_for_async(|| server.led_updates().len() == 3).await;
+    assert_eq!(
+        server.led_updates(),
+        vec![
+            (1, vec![color; 8]),
+            (2, vec![color; 8]),
+            (3, vec![color; 10])
+        ]
+    );
+    assert!(matches!(
+        client.set_color_by_id(10, &color).await,
+        Err(Error::ControllerNotFound(_))
+    ));
+    assert!(matches!(
+        client
+            .set_color_by_device_types(&[DeviceType::Gpu], &color)
+            .await,
+        Err(Error::ControllerNotFound(_))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_updates() {
+    let (server, client) = setup().await;
+    let tasks: Vec<_> = (0..4u8)
+        .map(|id| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                for i in 0..10 {
+                    client
+                        .set_color_by_id(id.into(), &RGBColor::new(id, i, 0))
+                        .await
+                        .unwrap();
+                    assert_eq!(client.profiles().await.unwrap(), Vec::<String>::new());
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    wait_for_async(|| {
+        server
+            .controllers()
+            .iter()
+            .all(|c| c.colors[0] == RGBColor::new(c.id as u8, 9, 0))
+    })
+    .await;
+    assert_eq!(server.led_updates().len(), 40);
+}
+
+#[tokio::test]
+async fn modes_and_profiles() {
+    let (server, client) = setup().await;
+    client.set_custom_mode(0).await.unwrap();
+    assert!(client.controllers()[0].active_mode().unwrap().is_direct());
+    client.set_mode(0, 0).await.unwrap();
+    assert_eq!(client.controllers()[0].active_mode, 0);
+    server.set_profiles(&["day"]);
+    client.save_profile("night").await.unwrap();
+    assert_eq!(client.profiles().await.unwrap(), vec!["day", "night"]);
+    client.load_profile("day").await.unwrap();
+    wait_for_async(|| {
+        server
+            .requests()
+            .iter()
+            .any(|(_, r)| *r == Request::LoadProfile("day".to_owned()))
+    })
+    .await;
+    wait_for_async(|| server.controllers()[0].active_mode == 0).await;
+}
+
+#[tokio::test]
+async fn notifications() {
+    let (server, client) = setup().await;
+    let mut notifications = client.subscribe();
+    server.set_controllers(vec![controller("Strip", DeviceType::LedStrip, 30)]);
+    let n = tokio::time::timeout(Duration::from_secs(1), notifications.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(n, Notification::DeviceListUpdated);
+    client.reload().await.unwrap();
+    assert_eq!(client.controllers()[0].name, "Strip");
+}
+
+#[tokio::test]
+async fn reconnect() {
+    let (server, client) = setup().await;
+    server.disconnect();
+    assert!(client.profiles().await.unwrap().is_empty());
+    let color = RGBColor::new(4, 5, 6);
+    client.set_color_by_id(1, &color).await.unwrap();
+    wait_for_async(|| server.controllers()[1].colors[0] == color).await;
+}
+
+#[tokio::test]
+async fn request_after_timeout() {
+    // the server does not reply to the first controller count request
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let received = Arc::new(AtomicUsize::new(0));
+    let counter = received.clone();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let counter = counter.clone();
+            thread::spawn(move || loop {
+                let mut buf = [0u8; PacketHeader::SIZE];
+                if stream.read_exact(&mut buf).is_err() {
+                    break;
+                }
+                let header = PacketHeader::unpack(&buf).unwrap();
+                let mut data = vec![0u8; header.len as usize];
+                stream.read_exact(&mut data).unwrap();
+                if counter.fetch_add(1, Ordering::SeqCst) > 0 {
+                    let response = Response::ControllerCount(5).pack(0).unwrap();
+                    let _ = stream.write_all(&encode_packet(0, header.packet_type, &response));
+                }
+            });
+        }
+    });
+    let client = AsyncOpenRGBClient::new(&addr);
+    client.set_timeout(Duration::from_millis(100));
+    client.set_retries(0);
+    let count = Request::RequestControllerCount;
+    assert!(client.request(0, &count).await.is_err());
+    assert_eq!(
+        client.request(0, &count).await.unwrap(),
+        Some(Response::ControllerCount(5))
+    );
+    assert_eq!(received.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn blocking_connector() {
+    let server = mock_server();
+    let client =
+        AsyncOpenRGBClient::with_connector(Box::new(BlockingConnector::new(server.connector())));
+    client.load().await.unwrap();
+    assert_eq!(client.controllers(), server.controllers());
+    let color = RGBColor::new(1, 2, 3);
+    client.set_color_by_name("Keyboard", &color).await.unwrap();
+    wait_for_async(|| server.led_updates() == vec![(3, vec![color; 10])]).await;
+}
+
+#[tokio::test]
+async fn capture() {
+    let server = mock_server();
+    let path = std::env::temp_dir().join(format!(
+        "rgbmon-async-capture-{}.bin",
+        server.addr().replace(':', "-")
+    ));
+    let client = AsyncOpenRGBClient::new(server.addr());
+    client.set_capture(path.to_str().unwrap()).unwrap();
+    client.load().await.unwrap();
+    let records = read_capture(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    // version, client name, count and 4 controllers sent, all but the name replied
+    assert_eq!(records.len(), 13);
+    assert_eq!(records[0].direction, Direction::Sent);
+    assert_eq!(records[0].packet_type, 40);
+    assert_eq!(records[1].direction, Direction::Received);
+    assert_eq!(records[1].packet_type, 40);
+}
+
+#[tokio::test]
+async fn timeout_keeps_other_requests() {
+    // the server does not reply to the first request and replies to the rest with a delay
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::spawn(move || {
+                let mut first = true;
+                loop {
+                    let mut buf = [0u8; PacketHeader::SIZE];
+                    if stream.read_exact(&mut buf).is_err() {
+                        break;
+                    }
+                    let header = PacketHeader::unpack(&buf).unwrap();
+                    let mut data = vec![0u8; header.len as usize];
+                    stream.read_exact(&mut data).unwrap();
+                    if std::mem::replace(&mut first, false) {
+                        continue;
+                    }
+                    let mut stream = stream.try_clone().unwrap();
+                    thread::spawn(move || {
+                        thread::sleep(Duration::from_millis(800));
+                        let response = Response::ControllerCount(5).pack(0).unwrap();
+                        let _ = stream.write_all(&encode_packet(0, header.packet_type, &response));
+                    });
+                }
+            });
+        }
+    });
+    let client = AsyncOpenRGBClient::new(&addr);
+    client.set_timeout(Duration::from_millis(1000));
+    client.set_retries(0);
+    let count = Request::RequestControllerCount;
+    let first = {
+        let client = client.clone();
+        let count = count.clone();
+        tokio::spawn(async move { client.request(0, &count).await })
+    };
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    // the response arrives after the first request is timed out
+    assert_eq!(
+        client.request(0, &count).await.unwrap(),
+        Some(Response::ControllerCount(5))
+    );
+    assert!(matches!(first.await.unwrap(), Err(Error::Io(_))));
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn set_leds_color() {
+    let (server, client) = setup().await;
+    let changed = RGBColor::new(7, 8, 9);
+    // LEDs, changed by other clients, keep their colors
+    let other = AsyncOpenRGBClient::new(server.addr());
+    other.load().await.unwrap();
+    other.set_led_color(3, 0, &changed).await.unwrap();
+    wait_for_async(|| server.controllers()[3].colors[0] == changed).await;
+    let color = RGBColor::new(1, 2, 3);
+    client
+        .set_leds_color(&["type=keyboard&led=LED 2".parse().unwrap()], &color)
+        .await
+        .unwrap();
+    wait_for_async(|| server.controllers()[3].colors[1] == color).await;
+    assert_eq!(server.controllers()[3].colors[0], changed);
+    assert_eq!(client.controllers()[3].colors[..2], [changed, color]);
+}
@@ -0,0 +1,89 @@
+use rgbmon::band::{Band, Effect, Fill};
+use rgbmon::gradient::Gradient;
+use rgbmon::RGBColor;
+use std::time::Duration;
+
+fn red() -> RGBColor {
+    RGBColor::new(255, 0, 0)
+}
+
+#[test]
+fn bands() {
+    let band = Band::new(20, 60, Fill::Solid(red())).unwrap();
+    assert!(!band.contains(19));
+    assert!(band.contains(20));
+    assert!(band.contains(60));
+    assert!(!band.contains(61));
+    assert_eq!(band.color(40, Duration::ZERO), red());
+    assert!(Band::new(60, 20, Fill::Solid(red())).is_err());
+    assert!(Band::new(0, 101, Fill::Solid(red())).is_err());
+}
+
+#[test]
+fn gradient_segment() {
+    let gradient = Gradient::new(&[(0., RGBColor::black()), (100., red())]).unwrap();
+    let band = Band::new(20, 60, Fill::Gradient(gradient)).unwrap();
+    // the stops are relative to the band
+    assert_eq!(band.color(20, Duration::ZERO), RGBColor::black());
+    assert_eq!(band.color(40, Duration::ZERO), RGBColor::new(128, 0, 0));
+    assert_eq!(band.color(60, Duration::ZERO), red());
+}
+
+#[test]
+fn effects() {
+    let flash = Effect::Flash {
+        color: red(),
+        period: Duration::from_secs(2),
+    };
+    assert_eq!(flash.color_at(Duration::ZERO), red());
+    assert_eq!(
+        flash.color_at(Duration::from_millis(1500)),
+        RGBColor::black()
+    );
+    assert_eq!(flash.color_at(Duration::from_millis(2500)), red());
+    let breathe = Effect::Breathe {
+        color: red(),
+        period: Duration::from_secs(2),
+    };
+    assert_eq!(breathe.color_at(Duration::ZERO), red());
+    assert_eq!(breathe.color_at(Duration::from_secs(1)), RGBColor::black());
+    let half = breathe.color_at(Duration::from_millis(500));
+    assert!(half.red > 0 && half.red < 255);
+}
+
+#[test]
+fn parse() {
+    let band: Band = "0-20:blue".parse().unwrap();
+    assert_eq!((band.from(), band.to()), (0, 20));
+    assert_eq!(band.fill(), &Fill::Solid(RGBColor::new(0, 0, 255)));
+    let band: Band = "20-60:0:blue,100:orange,oklab".parse().unwrap();
+    assert!(matches!(band.fill(), Fill::Gradient(g) if g.stops().len() == 2));
+    let band: Band = "85-100:flash(red,0.5)".parse().unwrap();
+    assert_eq!(
+        band.fill(),
+        &Fill::Effect(Effect::Flash {
+            color: red(),
+            period: Duration::from_millis(500)
+        })
+    );
+    assert_eq!(band.to_string(), "85-100:flash(FF0000,0.5)");
+    assert_eq!(band.to_string().parse::<Band>().unwrap(), band);
+    let band: Band = "90-100:breathe(rgb(255, 0, 0))".parse().unwrap();
+    assert_eq!(
+        band.fill(),
+        &Fill::Effect(Effect::Breathe {
+            color: red(),
+            period: Duration::from_secs(1)
+        })
+    );
+    assert!("0-20".parse::<Band>().is_err());
+    assert!("20:red".parse::<Band>().is_err());
+    assert!("0-200:red".parse::<Band>().is_err());
+    assert!("0-20:blink(red)".parse::<Band>().is_err());
+    assert!("0-20:flash(red,0)".parse::<Band>().is_err());
+    assert!("0-20:flash(red,-1)".parse::<Band>().is_err());
+    assert!("0-20:flash(red,inf)".parse::<Band>().is_err());
+    assert!("0-20:flash(red,NaN)".parse::<Band>().is_err());
+    assert!("0-20:flash(red,1e30)".parse::<Band>().is_err());
+    assert!("0-20:breathe(red,3601)".parse::<Band>().is_err());
+}
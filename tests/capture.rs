@@ -0,0 +1,93 @@
+use rgbmon::capture::{read_capture, unpack_capture, Direction, ReplayServer};
+use rgbmon::mock::{controller, MockServer};
+use rgbmon::{DeviceType, Error, OpenRGBClient, RGBColor, Request, Response, PROTOCOL_VERSION};
+use std::time::Duration;
+
+fn capture_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("rgbmon-test-{}-{}.cap", name, std::process::id()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Captures a session with the mock server: load and a single color update
+fn capture_session(name: &str, protocol: u32) -> (String, Vec<rgbmon::ControllerData>) {
+    let path = capture_path(name);
+    let server = MockServer::start(vec![
+        controller("Board", DeviceType::Motherboard, 4),
+        controller("Stick", DeviceType::Dram, 8),
+    ])
+    .unwrap();
+    server.set_protocol(protocol);
+    let mut client = OpenRGBClient::new();
+    client.timeout = Duration::from_millis(100);
+    client.set_path(server.addr());
+    client.set_capture(&path).unwrap();
+    client.load().unwrap();
+    // the controllers as loaded, the client updates the colors when they are set
+    let controllers = client.controllers.clone();
+    client.set_color_by_id(1, &RGBColor::new(1, 2, 3)).unwrap();
+    (path, controllers)
+}
+
+#[test]
+fn capture() {
+    let (path, controllers) = capture_session("capture", PROTOCOL_VERSION);
+    let records = read_capture(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let sent: Vec<Request> = records
+        .iter()
+        .filter(|r| r.direction == Direction::Sent)
+        .map(|r| Request::unpack(r.packet_type, PROTOCOL_VERSION, &r.data).unwrap())
+        .collect();
+    assert_eq!(sent[0], Request::RequestProtocolVersion(PROTOCOL_VERSION));
+    assert!(matches!(sent[1], Request::SetClientName(_)));
+    assert_eq!(sent[2], Request::RequestControllerCount);
+    assert_eq!(
+        sent[5],
+        Request::UpdateLeds(vec![RGBColor::new(1, 2, 3); 8])
+    );
+    let received: Vec<Response> = records
+        .iter()
+        .filter(|r| r.direction == Direction::Received)
+        .map(|r| Response::unpack(r.device_id, r.packet_type, PROTOCOL_VERSION, &r.data).unwrap())
+        .collect();
+    assert_eq!(received[0], Response::ProtocolVersion(PROTOCOL_VERSION));
+    assert_eq!(received[1], Response::ControllerCount(2));
+    assert_eq!(
+        received[3],
+        Response::ControllerData(Box::new(controllers[1].clone()))
+    );
+}
+
+#[test]
+fn replay() {
+    for protocol in &[0, 1, PROTOCOL_VERSION] {
+        let (path, controllers) = capture_session(&format!("replay-{}", protocol), *protocol);
+        let server = ReplayServer::open(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let mut client = OpenRGBClient::new();
+        client.timeout = Duration::from_millis(100);
+        client.set_path(server.addr());
+        client.load().unwrap();
+        assert_eq!(client.server_protocol, Some(*protocol));
+        assert_eq!(client.controllers, controllers);
+    }
+}
+
+#[test]
+fn malformed_capture() {
+    let (path, _) = capture_session("malformed", PROTOCOL_VERSION);
+    let data = std::fs::read(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(matches!(
+        unpack_capture(&data[..data.len() - 1]),
+        Err(Error::Malformed { .. })
+    ));
+    let mut invalid = data;
+    invalid[0] = b'?';
+    assert!(matches!(
+        unpack_capture(&invalid),
+        Err(Error::Malformed { offset: 0, .. })
+    ));
+}
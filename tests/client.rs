@@ -0,0 +1,220 @@
+mod common;
+
+use common::{setup, wait_for};
+use rgbmon::mock::{controller, MockServer};
+use rgbmon::{DeviceType, Error, Notification, OpenRGBClient, RGBColor, Request};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// UpdateLeds requests have no responses, waits until the server receives the expected ones
+fn wait_for_led_updates(server: &MockServer, expected: Vec<(u32, Vec<RGBColor>)>) {
+    wait_for(|| server.led_updates().len() >= expected.len());
+    assert_eq!(server.led_updates(), expected);
+}
+
+#[test]
+fn load() {
+    let (server, client) = setup();
+    assert_eq!(client.server_protocol, Some(rgbmon::PROTOCOL_VERSION));
+    assert_eq!(client.protocol, Some(rgbmon::PROTOCOL_VERSION));
+    assert_eq!(client.controllers, server.controllers());
+    assert!(server
+        .requests()
+        .iter()
+        .any(|(_, r)| matches!(r, Request::SetClientName(name) if name.starts_with("rgbmon "))));
+}
+
+#[test]
+fn load_protocol_0() {
+    let (server, _) = setup();
+    server.set_protocol(0);
+    let mut client = OpenRGBClient::new();
+    client.set_path(server.addr());
+    client.set_timeout(Duration::from_millis(200));
+    let started = Instant::now();
+    client.load().unwrap();
+    // the version probe is not retried
+    assert!(started.elapsed() < Duration::from_millis(600));
+    assert_eq!(client.server_protocol, Some(0));
+    assert_eq!(client.protocol, Some(0));
+    assert_eq!(client.controllers.len(), 4);
+}
+
+#[test]
+fn load_older_protocol() {
+    let server = MockServer::start(vec![controller("Board", DeviceType::Motherboard, 4)]).unwrap();
+    server.set_protocol(1);
+    let mut client = OpenRGBClient::new();
+    client.set_path(server.addr());
+    client.load().unwrap();
+    assert_eq!(client.server_protocol, Some(1));
+    assert_eq!(client.protocol, Some(1));
+    assert_eq!(client.controllers[0].metadata.vendor, "Mock");
+    assert!(matches!(
+        client.profiles(),
+        Err(Error::UnsupportedVersion {
+            required: 2,
+            negotiated: 1
+        })
+    ));
+}
+
+#[test]
+fn reload() {
+    let (server, mut client) = setup();
+    server.set_controllers(vec![controller("Strip", DeviceType::LedStrip, 30)]);
+    client.reload().unwrap();
+    assert_eq!(client.controllers.len(), 1);
+    assert_eq!(client.controllers[0].name, "Strip");
+    assert_eq!(client.controllers[0].leds.len(), 30);
+}
+
+#[test]
+fn set_color_selectors() {
+    let (server, mut client) = setup();
+    let color = RGBColor::new(1, 2, 3);
+    client.set_color_by_id(1, &color).unwrap();
+    wait_for_led_updates(&server, vec![(1, vec![color; 8])]);
+    server.clear_requests();
+    client.set_color_by_name("Stick", &color).unwrap();
+    wait_for_led_updates(&server, vec![(1, vec![color; 8]), (2, vec![color; 8])]);
+    server.clear_requests();
+    client
+        .set_color_by_device_type(DeviceType::Keyboard, &color)
+        .unwrap();
+    wait_for_led_updates(&server, vec![(3, vec![color; 10])]);
+    server.clear_requests();
+    client
+        .set_color_by_device_types(&[DeviceType::Motherboard, DeviceType::Gpu], &color)
+        .unwrap();
+    wait_for_led_updates(&server, vec![(0, vec![color; 4])]);
+    server.clear_requests();
+    client.set_color(&color).unwrap();
+    wait_for(|| server.led_updates().len() == 4);
+    assert_eq!(
+        server
+            .led_updates()
+            .iter()
+            .map(|(id, _)| *id)
+            .collect::<Vec<u32>>(),
+        vec![0, 1, 2, 3]
+    );
+    wait_for(|| server.controllers().iter().all(|c| c.colors[0] == color));
+}
+
+#[test]
+fn set_color_not_found() {
+    let (server, mut client) = setup();
+    let color = RGBColor::new(1, 2, 3);
+    assert!(matches!(
+        client.set_color_by_id(10, &color),
+        Err(Error::ControllerNotFound(_))
+    ));
+    assert!(matches!(
+        client.set_color_by_name("Mouse", &color),
+        Err(Error::ControllerNotFound(_))
+    ));
+    assert!(matches!(
+        client.set_color_by_device_types(&[DeviceType::Gpu, DeviceType::Mouse], &color),
+        Err(Error::ControllerNotFound(_))
+    ));
+    assert!(server.led_updates().is_empty());
+}
+
+#[test]
+fn set_colors_frames() {
+    let (server, mut client) = setup();
+    let frame: Vec<RGBColor> = (0..12).map(|i| RGBColor::new(i, i, i)).collect();
+    client.set_colors_by_id(0, |_| frame.clone()).unwrap();
+    // the frame is truncated to the number of LEDs
+    wait_for_led_updates(&server, vec![(0, frame[..4].to_vec())]);
+    server.clear_requests();
+    client
+        .set_colors_by_device_type(DeviceType::Dram, |c| {
+            vec![RGBColor::new(c.id as u8, 0, 0); c.leds.len()]
+        })
+        .unwrap();
+    wait_for_led_updates(
+        &server,
+        vec![
+            (1, vec![RGBColor::new(1, 0, 0); 8]),
+            (2, vec![RGBColor::new(2, 0, 0); 8]),
+        ],
+    );
+}
+
+#[test]
+fn zone_and_single_led() {
+    let (server, mut client) = setup();
+    let color = RGBColor::new(10, 20, 30);
+    client.set_zone_colors(3, 0, &[color, color]).unwrap();
+    client.set_led_color(3, 9, &color).unwrap();
+    assert!(matches!(
+        client.set_zone_colors(3, 1, &[color]),
+        Err(Error::NotFound(_))
+    ));
+    assert!(matches!(
+        client.set_led_color(3, 10, &color),
+        Err(Error::NotFound(_))
+    ));
+    wait_for(|| server.controllers()[3].colors[9] == color);
+    let colors = &server.controllers()[3].colors;
+    assert_eq!(colors[..2], [color, color]);
+    assert_eq!(colors[2], RGBColor::black());
+}
+
+#[test]
+fn modes() {
+    let (server, mut client) = setup();
+    assert_eq!(client.controllers[0].active_mode().unwrap().name, "Rainbow");
+    client.set_custom_mode(0).unwrap();
+    assert!(client.controllers[0].active_mode().unwrap().is_direct());
+    wait_for(|| server.controllers()[0].active_mode == 1);
+    client.set_mode(0, 0).unwrap();
+    client.save_mode(0, 0).unwrap();
+    assert_eq!(client.controllers[0].active_mode, 0);
+    wait_for(|| server.controllers()[0].active_mode == 0);
+    assert!(server
+        .requests()
+        .iter()
+        .any(|(id, r)| *id == 0 && matches!(r, Request::SaveMode { mode_id: 0, .. })));
+    assert!(matches!(client.set_mode(0, 5), Err(Error::NotFound(_))));
+}
+
+#[test]
+fn profiles() {
+    let (server, mut client) = setup();
+    server.set_profiles(&["day", "night"]);
+    assert_eq!(client.profiles().unwrap(), vec!["day", "night"]);
+    client.save_profile("evening").unwrap();
+    client.delete_profile("day").unwrap();
+    client.load_profile("night").unwrap();
+    assert_eq!(client.profiles().unwrap(), vec!["night", "evening"]);
+    assert!(server
+        .requests()
+        .iter()
+        .any(|(_, r)| *r == Request::LoadProfile("night".to_owned())));
+}
+
+#[test]
+fn device_list_updated() {
+    let (server, mut client) = setup();
+    assert!(client.poll_notifications().unwrap().is_empty());
+    server.set_controllers(vec![controller("Strip", DeviceType::LedStrip, 30)]);
+    wait_for(|| {
+        client
+            .poll_notifications()
+            .unwrap()
+            .contains(&Notification::DeviceListUpdated)
+    });
+    // notifications, received during regular calls, are queued
+    server.set_controllers(vec![controller("Fan", DeviceType::Cooler, 6)]);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(client.profiles().unwrap(), Vec::<String>::new());
+    assert_eq!(
+        client.poll_notifications().unwrap(),
+        vec![Notification::DeviceListUpdated]
+    );
+    client.reload().unwrap();
+    assert_eq!(client.controllers[0].name, "Fan");
+}
@@ -0,0 +1,201 @@
+use rgbmon::color::{Hsl, Hsv, LinearRgb, OkLab};
+use rgbmon::{Error, RGBColor};
+
+fn parse(s: &str) -> RGBColor {
+    s.parse().unwrap()
+}
+
+#[test]
+fn hex() {
+    assert_eq!(parse("112233"), RGBColor::new(0x11, 0x22, 0x33));
+    assert_eq!(parse("#AaBbCc"), RGBColor::new(0xaa, 0xbb, 0xcc));
+    assert_eq!(parse("#fff"), RGBColor::new(0xff, 0xff, 0xff));
+    assert_eq!(parse(" #09c "), RGBColor::new(0x00, 0x99, 0xcc));
+    assert_eq!(RGBColor::from(0x123456), RGBColor::new(0x12, 0x34, 0x56));
+    assert_eq!(u32::from(RGBColor::new(0x12, 0x34, 0x56)), 0x123456);
+    assert_eq!(
+        parse(&RGBColor::new(1, 2, 3).to_string()),
+        RGBColor::new(1, 2, 3)
+    );
+}
+
+#[test]
+fn names() {
+    assert_eq!(parse("red"), RGBColor::new(0xff, 0, 0));
+    assert_eq!(parse("Navy"), RGBColor::new(0, 0, 0x80));
+    assert_eq!(parse("rebeccapurple"), RGBColor::new(0x66, 0x33, 0x99));
+    assert_eq!(parse("dark slate gray"), parse("DarkSlateGrey"));
+    assert_eq!(parse("light_sky-blue"), RGBColor::new(0x87, 0xce, 0xfa));
+    // X11-only names
+    assert_eq!(parse("NavyBlue"), parse("navy"));
+    assert_eq!(parse("gray50"), RGBColor::new(0x7f, 0x7f, 0x7f));
+    assert_eq!(parse("Dark Olive Green 3"), RGBColor::new(0xa2, 0xcd, 0x5a));
+    // CSS values are used, where X11 differs
+    assert_eq!(parse("gray"), RGBColor::new(0x80, 0x80, 0x80));
+    assert_eq!(parse("green"), RGBColor::new(0, 0x80, 0));
+}
+
+#[test]
+fn functions() {
+    assert_eq!(parse("rgb(1, 2, 3)"), RGBColor::new(1, 2, 3));
+    assert_eq!(parse("RGB(255 0 128)"), RGBColor::new(255, 0, 128));
+    assert_eq!(parse("rgb(100%,50%,0%)"), RGBColor::new(255, 128, 0));
+    assert_eq!(parse("hsl(0, 100%, 50%)"), RGBColor::new(255, 0, 0));
+    assert_eq!(parse("hsl(120deg 100% 25%)"), RGBColor::new(0, 128, 0));
+    assert_eq!(parse("hsl(240, 100%, 50%)"), RGBColor::new(0, 0, 255));
+    assert_eq!(parse("hsl(-120, 100%, 50%)"), RGBColor::new(0, 0, 255));
+    assert_eq!(parse("hsl(270, 50%, 40%)"), parse("#663399"));
+    assert_eq!(parse("hsl(0, 0%, 100%)"), parse("white"));
+}
+
+#[test]
+fn invalid() {
+    for s in &[
+        "",
+        "#ffx",
+        "#ff",
+        "#fffff",
+        "fff",
+        "12345g",
+        "1234567",
+        "redish",
+        "rgb(1,2)",
+        "rgb(1,2,256)",
+        "rgb(1,2,3",
+        "rgb(a,b,c)",
+        "hsl(0, 120%, 50%)",
+        "hsl(nan, 0%, 0%)",
+    ] {
+        match s.parse::<RGBColor>() {
+            Err(Error::InvalidValue(msg)) => assert!(msg.contains("color"), "{}", msg),
+            v => panic!("{}: {:?}", s, v),
+        }
+    }
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 0.002
+}
+
+fn samples() -> Vec<RGBColor> {
+    let mut v = Vec::new();
+    for r in (0..=255).step_by(51) {
+        for g in (0..=255).step_by(51) {
+            for b in (0..=255).step_by(85) {
+                v.push(RGBColor::new(r, g, b));
+            }
+        }
+    }
+    v.push(RGBColor::new(1, 2, 3));
+    v.push(RGBColor::new(254, 1, 128));
+    v
+}
+
+#[test]
+fn hsv() {
+    let hsv = RGBColor::new(0, 255, 0).to_hsv();
+    assert_eq!(
+        hsv,
+        Hsv {
+            hue: 120.,
+            saturation: 1.,
+            value: 1.
+        }
+    );
+    let hsv = parse("#804080").to_hsv();
+    assert!(close(hsv.hue, 300.) && close(hsv.saturation, 0.5) && close(hsv.value, 0.502));
+    assert_eq!(parse("gray").to_hsv().saturation, 0.);
+    assert_eq!(
+        RGBColor::from_hsv(Hsv {
+            hue: -60.,
+            saturation: 1.,
+            value: 1.
+        }),
+        parse("magenta")
+    );
+    for c in samples() {
+        assert_eq!(RGBColor::from_hsv(c.to_hsv()), c);
+    }
+}
+
+#[test]
+fn hsl() {
+    let hsl = parse("#663399").to_hsl();
+    assert!(close(hsl.hue, 270.) && close(hsl.saturation, 0.5) && close(hsl.lightness, 0.4));
+    assert_eq!(
+        RGBColor::from_hsl(Hsl {
+            hue: 0.,
+            saturation: 1.,
+            lightness: 0.75
+        }),
+        RGBColor::new(255, 128, 128)
+    );
+    assert_eq!(parse("white").to_hsl().lightness, 1.);
+    for c in samples() {
+        assert_eq!(RGBColor::from_hsl(c.to_hsl()), c);
+    }
+}
+
+#[test]
+fn linear() {
+    let lin = RGBColor::new(255, 128, 0).to_linear();
+    assert!(close(lin.red, 1.) && close(lin.green, 0.2158) && close(lin.blue, 0.));
+    assert_eq!(
+        RGBColor::from_linear(LinearRgb {
+            red: 0.5,
+            green: 2.,
+            blue: -1.
+        }),
+        RGBColor::new(188, 255, 0)
+    );
+    for c in samples() {
+        assert_eq!(RGBColor::from_linear(c.to_linear()), c);
+    }
+}
+
+#[test]
+fn oklab() {
+    let white = parse("white").to_oklab();
+    assert!(close(white.l, 1.) && close(white.a, 0.) && close(white.b, 0.));
+    let red = parse("red").to_oklab();
+    assert!(close(red.l, 0.628) && close(red.a, 0.2249) && close(red.b, 0.1258));
+    assert_eq!(
+        RGBColor::from_oklab(OkLab {
+            l: 0.,
+            a: 0.,
+            b: 0.
+        }),
+        RGBColor::black()
+    );
+    for c in samples() {
+        assert_eq!(RGBColor::from_oklab(c.to_oklab()), c);
+    }
+}
+
+#[test]
+fn kelvin() {
+    assert_eq!(RGBColor::from_kelvin(6600.), RGBColor::new(255, 255, 255));
+    let candle = RGBColor::from_kelvin(1900.);
+    assert_eq!(candle.red, 255);
+    assert!(candle.green < 140 && candle.blue < 60);
+    let sky = RGBColor::from_kelvin(15000.);
+    assert!(sky.blue == 255 && sky.red < 200);
+    assert_eq!(RGBColor::from_kelvin(0.), RGBColor::from_kelvin(1000.));
+}
+
+#[test]
+fn brightness() {
+    let c = parse("orange");
+    assert_eq!(c.with_brightness(1.), c);
+    assert_eq!(c.with_brightness(0.), RGBColor::black());
+    assert_eq!(c.with_brightness(-1.), RGBColor::black());
+    let half = c.with_brightness(0.5);
+    assert!((half.to_hsv().hue - c.to_hsv().hue).abs() < 1.);
+    // within the 8-bit quantization step
+    assert!((half.to_linear().green - c.to_linear().green / 2.).abs() < 0.005);
+    // about 0.5 in linear light
+    assert_eq!(
+        parse("#bcbcbc").with_brightness(2.),
+        RGBColor::new(255, 255, 255)
+    );
+}
@@ -0,0 +1,53 @@
+//! Fixtures, shared by the integration tests
+#![allow(dead_code)]
+use rgbmon::mock::{controller, MockServer};
+use rgbmon::{DeviceType, OpenRGBClient};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the server to process requests without responses (e.g. LED updates)
+pub const WAIT_TIMEOUT: Duration = Duration::from_secs(3);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The mock server with a motherboard, two DRAM sticks and a keyboard
+pub fn mock_server() -> MockServer {
+    MockServer::start(vec![
+        controller("Board", DeviceType::Motherboard, 4),
+        controller("Stick", DeviceType::Dram, 8),
+        controller("Stick", DeviceType::Dram, 8),
+        controller("Keyboard", DeviceType::Keyboard, 10),
+    ])
+    .unwrap()
+}
+
+/// The mock server and a client, loaded from it
+pub fn setup() -> (MockServer, OpenRGBClient) {
+    let server = mock_server();
+    let mut client = OpenRGBClient::new();
+    client.set_path(server.addr());
+    client.load().unwrap();
+    (server, client)
+}
+
+/// Waits until the condition is true, panics on timeout
+pub fn wait_for<F: FnMut() -> bool>(mut f: F) {
+    let start = Instant::now();
+    while !f() {
+        if start.elapsed() > WAIT_TIMEOUT {
+            panic!("timeout");
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The same as wait_for, but does not block the tokio runtime
+pub async fn wait_for_async<F: FnMut() -> bool>(mut f: F) {
+    let start = Instant::now();
+    while !f() {
+        if start.elapsed() > WAIT_TIMEOUT {
+            panic!("timeout");
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
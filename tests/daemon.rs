@@ -0,0 +1,402 @@
+mod common;
+
+use common::wait_for;
+use rgbmon::mock::{controller, MockServer};
+use rgbmon::{DeviceType, RGBColor, Request};
+use std::process::{Child, Command, Stdio};
+
+struct Daemon {
+    child: Child,
+    pid_file: String,
+}
+
+impl Daemon {
+    fn start(server: &MockServer, args: &[&str]) -> Self {
+        let pid_file = std::env::temp_dir()
+            .join(format!(
+                "rgbmon-test-{}.pid",
+                server.addr().replace(':', "-")
+            ))
+            .to_string_lossy()
+            .into_owned();
+        let child = Command::new(env!("CARGO_BIN_EXE_rgbmon"))
+            .args(["-v", "-s", "0.1", "--connect", server.addr()])
+            .args(["--pid-file", &pid_file])
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        Self { child, pid_file }
+    }
+
+    fn signal(&self, sig: &str) {
+        Command::new("kill")
+            .args([&format!("-{}", sig), &self.child.id().to_string()])
+            .status()
+            .unwrap();
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.pid_file);
+    }
+}
+
+/// The default device types include GPUs, so unlike the other tests, the server has one
+fn mock_server() -> MockServer {
+    MockServer::start(vec![
+        controller("Board", DeviceType::Motherboard, 4),
+        controller("Stick", DeviceType::Dram, 8),
+        controller("Keyboard", DeviceType::Keyboard, 10),
+        controller("Card", DeviceType::Gpu, 2),
+    ])
+    .unwrap()
+}
+
+fn updated_ids(server: &MockServer) -> Vec<u32> {
+    let mut ids: Vec<u32> = server.led_updates().iter().map(|(id, _)| *id).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+fn active_modes(server: &MockServer) -> Vec<i32> {
+    server.controllers().iter().map(|c| c.active_mode).collect()
+}
+
+fn loaded_profiles(server: &MockServer) -> Vec<String> {
+    server
+        .requests()
+        .into_iter()
+        .filter_map(|(_, r)| match r {
+            Request::LoadProfile(name) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn default_device_types() {
+    let server = mock_server();
+    // any load is below the threshold, so the color is always the default one
+    let _daemon = Daemon::start(&server, &["--default-color", "100:#123"]);
+    wait_for(|| updated_ids(&server) == vec![0, 1, 3]);
+    let color = RGBColor::new(0x11, 0x22, 0x33);
+    for (id, colors) in server.led_updates() {
+        assert_eq!(
+            colors,
+            vec![color; server.controllers()[id as usize].leds.len()]
+        );
+    }
+    assert!(server.controllers()[2]
+        .colors
+        .iter()
+        .all(|c| *c == RGBColor::black()));
+}
+
+#[test]
+fn selected_device_types() {
+    let server = mock_server();
+    let _daemon = Daemon::start(
+        &server,
+        &[
+            "--default-color",
+            "100:445566",
+            "--device-types",
+            "keyboard,2",
+        ],
+    );
+    wait_for(|| updated_ids(&server) == vec![2, 3]);
+    wait_for(|| server.controllers()[2].colors[9] == RGBColor::new(0x44, 0x55, 0x66));
+}
+
+#[test]
+fn selected_devices() {
+    let server = mock_server();
+    let _daemon = Daemon::start(
+        &server,
+        &[
+            "--default-color",
+            "100:445566",
+            "--devices",
+            "name=Bo*;type=gpu",
+        ],
+    );
+    wait_for(|| updated_ids(&server) == vec![0, 3]);
+    wait_for(|| server.controllers()[3].colors[1] == RGBColor::new(0x44, 0x55, 0x66));
+}
+
+#[test]
+fn selected_leds() {
+    let server = mock_server();
+    let _daemon = Daemon::start(
+        &server,
+        &[
+            "--default-color",
+            "100:445566",
+            "--device-types",
+            "keyboard,gpu",
+            "--leds",
+            "type=keyboard&led=LED [2-3]",
+            "--leds",
+            "name=Card&led=LED 1",
+        ],
+    );
+    let color = RGBColor::new(0x44, 0x55, 0x66);
+    let black = RGBColor::black();
+    wait_for(|| server.controllers()[3].colors == vec![color, black]);
+    wait_for(|| server.controllers()[2].colors[..4] == [black, color, color, black]);
+    // a single frame per controller
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|(_, r)| matches!(r, Request::UpdateSingleLed { .. })));
+}
+
+#[test]
+fn selected_leds_of_device_types() {
+    let server = mock_server();
+    let _daemon = Daemon::start(
+        &server,
+        &["--default-color", "100:445566", "--leds", "led=LED 1"],
+    );
+    let color = RGBColor::new(0x44, 0x55, 0x66);
+    wait_for(|| updated_ids(&server) == vec![0, 1, 3]);
+    for id in [0, 1, 3] {
+        wait_for(|| server.controllers()[id].colors[..2] == [color, RGBColor::black()]);
+    }
+    // keyboards are not in the default device types
+    assert!(server.controllers()[2]
+        .colors
+        .iter()
+        .all(|c| *c == RGBColor::black()));
+}
+
+#[test]
+fn device_gradients() {
+    let server = mock_server();
+    // single-stop gradients give the same color for any load
+    let _daemon = Daemon::start(
+        &server,
+        &[
+            "--gradient",
+            "0:#112233",
+            "--gradient",
+            "type=gpu@0:#445566,100:#445566",
+        ],
+    );
+    wait_for(|| updated_ids(&server) == vec![0, 1, 3]);
+    wait_for(|| server.controllers()[0].colors[0] == RGBColor::new(0x11, 0x22, 0x33));
+    wait_for(|| server.controllers()[1].colors[7] == RGBColor::new(0x11, 0x22, 0x33));
+    wait_for(|| server.controllers()[3].colors[1] == RGBColor::new(0x44, 0x55, 0x66));
+}
+
+#[test]
+fn flashing_band() {
+    let server = mock_server();
+    let _daemon = Daemon::start(
+        &server,
+        &[
+            "--device-types",
+            "motherboard",
+            "--band",
+            "0-100:flash(#ff0000,0.2)",
+        ],
+    );
+    let red = RGBColor::new(255, 0, 0);
+    // the colors change between the load samples
+    wait_for(|| {
+        let updates = server.led_updates();
+        updates.iter().any(|(_, c)| c[0] == red)
+            && updates.iter().any(|(_, c)| c[0] == RGBColor::black())
+    });
+}
+
+#[test]
+fn invalid_default_color() {
+    for color in &["20:#ffx", "20", "x:red", "200:red"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_rgbmon"))
+            .args(["--default-color", color])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{}", color);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("--default-color"), "{}", stderr);
+    }
+}
+
+#[test]
+fn suspend_and_resume() {
+    let server = mock_server();
+    let daemon = Daemon::start(&server, &["--default-color", "100:112233"]);
+    let color = RGBColor::new(0x11, 0x22, 0x33);
+    wait_for(|| server.controllers()[0].colors[0] == color);
+    daemon.signal("USR1");
+    wait_for(|| server.controllers()[0].colors[0] == RGBColor::black());
+    daemon.signal("HUP");
+    wait_for(|| server.controllers()[0].colors[0] == color);
+}
+
+#[test]
+fn suspend_profile() {
+    let server = mock_server();
+    // two channels share the client, the profile is loaded once
+    let daemon = Daemon::start(
+        &server,
+        &[
+            "--gradient",
+            "0:#112233",
+            "--gradient",
+            "type=gpu@0:#445566",
+            "--suspend-profile",
+            "Idle",
+        ],
+    );
+    wait_for(|| server.controllers()[3].colors[0] == RGBColor::new(0x44, 0x55, 0x66));
+    server.clear_requests();
+    daemon.signal("USR1");
+    wait_for(|| !loaded_profiles(&server).is_empty());
+    daemon.signal("HUP");
+    // the suspend is finished once the colors are set again
+    wait_for(|| updated_ids(&server) == vec![0, 1, 3]);
+    assert_eq!(loaded_profiles(&server), vec!["Idle"]);
+    assert!(server
+        .led_updates()
+        .iter()
+        .all(|(_, colors)| colors.iter().all(|c| *c != RGBColor::black())));
+}
+
+#[test]
+fn exit_profile() {
+    let server = mock_server();
+    let mut daemon = Daemon::start(
+        &server,
+        &[
+            "--gradient",
+            "0:#112233",
+            "--gradient",
+            "type=gpu@0:#445566",
+            "--direct-mode",
+            "--exit-profile",
+            "Default",
+        ],
+    );
+    wait_for(|| server.controllers()[3].colors[0] == RGBColor::new(0x44, 0x55, 0x66));
+    daemon.signal("TERM");
+    assert!(daemon.child.wait().unwrap().success());
+    wait_for(|| !loaded_profiles(&server).is_empty());
+    assert_eq!(loaded_profiles(&server), vec!["Default"]);
+    // the profile is loaded after all channels restore the modes
+    let requests = server.requests();
+    let last_mode = requests
+        .iter()
+        .rposition(|(_, r)| matches!(r, Request::UpdateMode { .. }))
+        .unwrap();
+    assert!(matches!(
+        requests[last_mode + 1..],
+        [(_, Request::LoadProfile(_))]
+    ));
+}
+
+#[test]
+fn direct_mode_restored_on_exit() {
+    let server = mock_server();
+    let mut daemon = Daemon::start(&server, &["--default-color", "100:112233", "--direct-mode"]);
+    wait_for(|| server.controllers()[0].colors[0] == RGBColor::new(0x11, 0x22, 0x33));
+    assert_eq!(active_modes(&server), vec![1, 1, 0, 1]);
+    daemon.signal("TERM");
+    assert!(daemon.child.wait().unwrap().success());
+    wait_for(|| active_modes(&server) == vec![0, 0, 0, 0]);
+    assert!(server
+        .requests()
+        .iter()
+        .any(|(id, r)| *id == 3 && matches!(r, Request::UpdateMode { mode_id: 0, .. })));
+}
+
+#[test]
+fn direct_mode_restored_after_reload() {
+    let server = mock_server();
+    let mut daemon = Daemon::start(&server, &["--default-color", "100:112233", "--direct-mode"]);
+    wait_for(|| server.controllers()[0].active_mode == 1);
+    let custom_modes = |server: &MockServer| {
+        server
+            .requests()
+            .iter()
+            .filter(|(id, r)| *id == 0 && matches!(r, Request::SetCustomMode))
+            .count()
+    };
+    assert_eq!(custom_modes(&server), 1);
+    daemon.signal("HUP");
+    // the controllers are switched to the custom mode again after the reload
+    wait_for(|| custom_modes(&server) == 2);
+    daemon.signal("TERM");
+    assert!(daemon.child.wait().unwrap().success());
+    wait_for(|| server.controllers()[0].active_mode == 0);
+}
+
+#[test]
+fn direct_mode_restored_after_hot_plug() {
+    let mut controllers = vec![
+        controller("Board", DeviceType::Motherboard, 4),
+        controller("Stick", DeviceType::Dram, 8),
+        controller("Stick", DeviceType::Dram, 8),
+    ];
+    controllers[1].active_mode = 1;
+    let server = MockServer::start(controllers).unwrap();
+    let mut daemon = Daemon::start(&server, &["--default-color", "100:112233", "--direct-mode"]);
+    wait_for(|| active_modes(&server) == vec![1, 1, 1]);
+    // a device, plugged in before the others, shifts their ids
+    let mut controllers = server.controllers();
+    controllers.insert(0, controller("Strip", DeviceType::LedStrip, 5));
+    server.set_controllers(controllers);
+    wait_for(|| server.controllers()[0].active_mode == 1);
+    daemon.signal("TERM");
+    assert!(daemon.child.wait().unwrap().success());
+    wait_for(|| active_modes(&server) == vec![0, 0, 1, 0]);
+}
+
+#[test]
+fn device_gradients_after_hot_plug() {
+    let server = mock_server();
+    let _daemon = Daemon::start(
+        &server,
+        &[
+            "--gradient",
+            "0:#112233",
+            "--gradient",
+            "type=gpu@0:#445566,100:#445566",
+            "--direct-mode",
+        ],
+    );
+    wait_for(|| active_modes(&server) == vec![1, 1, 0, 1]);
+    server.clear_requests();
+    let mut controllers = server.controllers();
+    controllers.push(controller("Stick", DeviceType::Dram, 8));
+    controllers.push(controller("Card", DeviceType::Gpu, 2));
+    server.set_controllers(controllers);
+    // both channels set up the new devices
+    wait_for(|| active_modes(&server) == vec![1, 1, 0, 1, 1, 1]);
+    wait_for(|| server.controllers()[4].colors[0] == RGBColor::new(0x11, 0x22, 0x33));
+    wait_for(|| server.controllers()[5].colors[1] == RGBColor::new(0x44, 0x55, 0x66));
+    // the device list is reloaded once for all channels
+    let reloads = server
+        .requests()
+        .iter()
+        .filter(|(_, r)| matches!(r, Request::RequestControllerCount))
+        .count();
+    assert_eq!(reloads, 1);
+}
+
+#[test]
+fn replay_in_background() {
+    let output = Command::new(env!("CARGO_BIN_EXE_rgbmon"))
+        .args(["-D", "--replay", "/nonexistent"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--replay"), "{}", stderr);
+}
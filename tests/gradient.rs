@@ -0,0 +1,82 @@
+use rgbmon::gradient::{interpolate, ColorSpace, Gradient, HuePath};
+use rgbmon::RGBColor;
+
+fn red() -> RGBColor {
+    RGBColor::new(255, 0, 0)
+}
+
+fn blue() -> RGBColor {
+    RGBColor::new(0, 0, 255)
+}
+
+#[test]
+fn stops() {
+    let gradient =
+        Gradient::new(&[(100., blue()), (0., red()), (50., RGBColor::new(0, 255, 0))]).unwrap();
+    assert_eq!(gradient.stops()[0], (0., red()));
+    assert_eq!(gradient.color_at(0.), red());
+    assert_eq!(gradient.color_at(25.), RGBColor::new(128, 128, 0));
+    assert_eq!(gradient.color_at(50.), RGBColor::new(0, 255, 0));
+    assert_eq!(gradient.color_at(100.), blue());
+    // outside of the stops
+    let gradient = Gradient::new(&[(20., red()), (80., blue())]).unwrap();
+    assert_eq!(gradient.color_at(10.), red());
+    assert_eq!(gradient.color_at(90.), blue());
+    assert!(Gradient::new(&[]).is_err());
+    assert!(Gradient::new(&[(101., red())]).is_err());
+}
+
+#[test]
+fn spaces() {
+    let rgb = interpolate(&red(), &blue(), 0.5, ColorSpace::Rgb, HuePath::Shortest);
+    assert_eq!(rgb, RGBColor::new(128, 0, 128));
+    let hsv = interpolate(&red(), &blue(), 0.5, ColorSpace::Hsv, HuePath::Shortest);
+    assert_eq!(hsv, RGBColor::new(255, 0, 255));
+    let hsv = interpolate(&red(), &blue(), 0.5, ColorSpace::Hsv, HuePath::Longest);
+    assert_eq!(hsv, RGBColor::new(0, 255, 0));
+    // OKLab keeps the lightness between the stops, RGB mix is darker
+    let oklab = interpolate(&red(), &blue(), 0.5, ColorSpace::OkLab, HuePath::Shortest);
+    assert!(oklab.to_oklab().l > rgb.to_oklab().l);
+    // grays take the hue of the other color
+    let gray = interpolate(
+        &RGBColor::new(255, 255, 255),
+        &blue(),
+        0.5,
+        ColorSpace::Hsv,
+        HuePath::Shortest,
+    );
+    assert_eq!(gray, RGBColor::new(128, 128, 255));
+}
+
+#[test]
+fn rainbow() {
+    let rainbow = Gradient::rainbow();
+    assert_eq!(rainbow.color_at(100.), RGBColor::new(235, 0, 0));
+    let violet = rainbow.color_at(0.);
+    assert!(violet.blue > violet.red && violet.green == 0);
+    // goes through green
+    let middle = rainbow.color_at(50.);
+    assert!(middle.green > middle.red && middle.green > middle.blue);
+}
+
+#[test]
+fn parse() {
+    let gradient: Gradient = "0:0000FF, 50%:rgb(0,255,0), 100:red, hsv, longest"
+        .parse()
+        .unwrap();
+    assert_eq!(gradient.stops().len(), 3);
+    assert_eq!(gradient.stops()[1], (50., RGBColor::new(0, 255, 0)));
+    assert_eq!(gradient.space(), ColorSpace::Hsv);
+    assert_eq!(gradient.hue_path(), HuePath::Longest);
+    assert_eq!(
+        gradient.to_string(),
+        "0:0000FF,50:00FF00,100:FF0000,hsv,longest"
+    );
+    assert_eq!(gradient.to_string().parse::<Gradient>().unwrap(), gradient);
+    let gradient: Gradient = "0:blue,100:red".parse().unwrap();
+    assert_eq!(gradient.space(), ColorSpace::Rgb);
+    assert!("".parse::<Gradient>().is_err());
+    assert!("0:blue,lab".parse::<Gradient>().is_err());
+    assert!("x:blue".parse::<Gradient>().is_err());
+    assert!("0:nocolor".parse::<Gradient>().is_err());
+}
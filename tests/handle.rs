@@ -0,0 +1,111 @@
+mod common;
+
+use common::wait_for;
+use rgbmon::handle::ClientHandle;
+use rgbmon::mock::MockServer;
+use rgbmon::{DeviceType, Error, RGBColor};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+fn setup() -> (MockServer, ClientHandle) {
+    let (server, client) = common::setup();
+    (server, ClientHandle::new(client))
+}
+
+#[test]
+fn load() {
+    let (server, handle) = setup();
+    assert_eq!(handle.controllers().unwrap(), server.controllers());
+    assert_eq!(handle.protocol().unwrap(), Some(rgbmon::PROTOCOL_VERSION));
+}
+
+#[test]
+fn clones_from_threads() {
+    let (server, handle) = setup();
+    let threads: Vec<_> = (0..4u8)
+        .map(|id| {
+            let handle = handle.clone();
+            thread::spawn(move || {
+                for i in 0..10 {
+                    handle
+                        .set_color_by_id(id.into(), &RGBColor::new(id, i, 0))
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    wait_for(|| {
+        server
+            .controllers()
+            .iter()
+            .all(|c| c.colors[0] == RGBColor::new(c.id as u8, 9, 0))
+    });
+    assert!(matches!(
+        handle.set_color_by_device_types(&[DeviceType::Gpu], &RGBColor::black()),
+        Err(Error::ControllerNotFound(_))
+    ));
+}
+
+#[test]
+fn colors_by_id() {
+    let (server, handle) = setup();
+    handle
+        .set_colors_by_id(3, |c| {
+            (0..c.leds.len())
+                .map(|i| RGBColor::new(i as u8, 0, 0))
+                .collect()
+        })
+        .unwrap();
+    wait_for(|| server.controllers()[3].colors[9] == RGBColor::new(9, 0, 0));
+    assert!(matches!(
+        handle.set_colors_by_id(5, |_| Vec::new()),
+        Err(Error::ControllerNotFound(_))
+    ));
+}
+
+#[test]
+fn request_timeout() {
+    let (_server, handle) = setup();
+    let busy = handle.clone();
+    let t = thread::spawn(move || {
+        busy.call(|_| {
+            thread::sleep(Duration::from_millis(300));
+            Ok(())
+        })
+    });
+    thread::sleep(Duration::from_millis(50));
+    let executed = Arc::new(AtomicBool::new(false));
+    let e = executed.clone();
+    let result = handle.call_timeout(Duration::from_millis(50), move |_| {
+        e.store(true, Ordering::SeqCst);
+        Ok(())
+    });
+    match result {
+        Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::TimedOut),
+        v => panic!("{:?}", v),
+    }
+    t.join().unwrap().unwrap();
+    // the request expired in the queue and has not been executed
+    assert!(handle.controllers().is_ok());
+    assert!(!executed.load(Ordering::SeqCst));
+    let fast = handle.with_timeout(Duration::from_secs(1));
+    assert_eq!(fast.timeout(), Duration::from_secs(1));
+    assert_eq!(fast.profiles().unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn modes_and_profiles() {
+    let (server, handle) = setup();
+    handle.set_custom_mode(0).unwrap();
+    wait_for(|| server.controllers()[0].active_mode == 1);
+    handle.set_mode(0, 0).unwrap();
+    server.set_profiles(&["day"]);
+    handle.save_profile("night").unwrap();
+    assert_eq!(handle.profiles().unwrap(), vec!["day", "night"]);
+    wait_for(|| server.controllers()[0].active_mode == 0);
+}
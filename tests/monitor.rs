@@ -0,0 +1,184 @@
+mod common;
+
+use common::{setup, wait_for};
+use rgbmon::band::{Band, Fill};
+use rgbmon::gradient::Gradient;
+use rgbmon::handle::ClientHandle;
+use rgbmon::monitor::{self, ColorMap, GradientColorMap, Metric, Monitor, OpenRGBOutput, Output};
+use rgbmon::selector::Selector;
+use rgbmon::{DeviceType, Error, RGBColor, Request};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Default)]
+struct Recorder {
+    colors: Arc<Mutex<Vec<RGBColor>>>,
+    refresh: Arc<Mutex<bool>>,
+    /// Colors are recorded, but fail to set
+    fail: Arc<Mutex<bool>>,
+}
+
+impl Recorder {
+    fn colors(&self) -> Vec<RGBColor> {
+        self.colors.lock().unwrap().clone()
+    }
+}
+
+impl Output for Recorder {
+    fn set_color(&mut self, color: &RGBColor) -> Result<(), Error> {
+        self.colors.lock().unwrap().push(*color);
+        if *self.fail.lock().unwrap() {
+            Err(Error::Io(io::Error::other("failed")))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn refresh(&mut self) -> Result<bool, Error> {
+        let mut refresh = self.refresh.lock().unwrap();
+        Ok(std::mem::replace(&mut *refresh, false))
+    }
+}
+
+/// Returns the values one by one, then fails
+struct Values(Vec<u8>);
+
+impl Metric for Values {
+    fn sample(&mut self, interval: Duration) -> Result<u8, Error> {
+        thread::sleep(interval);
+        if self.0.is_empty() {
+            Err(Error::Io(io::Error::other("no more")))
+        } else {
+            Ok(self.0.remove(0))
+        }
+    }
+}
+
+fn gray(value: u8) -> RGBColor {
+    RGBColor::new(value, value, value)
+}
+
+#[test]
+fn load_to_color() {
+    let output = Recorder::default();
+    let mut monitor = Monitor::new(gray, output.clone());
+    monitor.set_load_diff(5);
+    assert_eq!(monitor.load(), None);
+    monitor.update_load(3);
+    // less than load diff is treated as zero
+    assert_eq!(monitor.load(), Some(0));
+    monitor.update_load(4);
+    monitor.update_load(50);
+    monitor.update_load(53);
+    monitor.update_load(45);
+    assert_eq!(output.colors(), vec![gray(0), gray(50), gray(45)]);
+    assert_eq!(monitor.color(), Some(gray(45)));
+    // the same color is not set twice unless forced
+    monitor.set_load(45);
+    monitor.apply(true);
+    assert_eq!(output.colors().len(), 4);
+}
+
+#[test]
+fn suspend_and_resume() {
+    let output = Recorder::default();
+    let mut monitor = Monitor::new(gray, output.clone());
+    monitor.set_load(10);
+    monitor.stop();
+    assert!(!monitor.is_active());
+    monitor.set_load(20);
+    assert_eq!(output.colors(), vec![gray(10), RGBColor::black()]);
+    monitor.start();
+    assert_eq!(output.colors().last(), Some(&gray(20)));
+    *output.refresh.lock().unwrap() = true;
+    monitor.refresh();
+    monitor.refresh();
+    assert_eq!(output.colors().len(), 4);
+}
+
+#[test]
+fn failed_colors_retried_on_samples() {
+    let output = Recorder::default();
+    let mut monitor = Monitor::new(gray, output.clone());
+    *output.fail.lock().unwrap() = true;
+    monitor.update_load(10);
+    monitor.apply(false);
+    monitor.apply(false);
+    assert_eq!(output.colors(), vec![gray(10)]);
+    assert_eq!(monitor.color(), None);
+    // the same load is sampled again
+    *output.fail.lock().unwrap() = false;
+    monitor.update_load(10);
+    assert_eq!(output.colors(), vec![gray(10), gray(10)]);
+    assert_eq!(monitor.color(), Some(gray(10)));
+}
+
+#[test]
+fn rainbow_bands() {
+    let mut color_map = GradientColorMap::default();
+    let low = RGBColor::new(0, 0, 0x20);
+    assert_ne!(color_map.color(10), low);
+    color_map.bands = vec![
+        Band::new(0, 10, Fill::Solid(low)).unwrap(),
+        Band::new(5, 20, Fill::Solid(gray(1))).unwrap(),
+    ];
+    assert!(!color_map.is_animated());
+    color_map
+        .bands
+        .push(Band::new(90, 100, "flash(red,2)".parse().unwrap()).unwrap());
+    assert!(color_map.is_animated());
+    assert_eq!(color_map.color(10), low);
+    assert_eq!(color_map.color(11), gray(1));
+    assert_ne!(color_map.color(50), color_map.color(21));
+    assert_eq!(
+        color_map.color_at_time(95, Duration::from_millis(500)),
+        RGBColor::new(255, 0, 0)
+    );
+    assert_eq!(
+        color_map.color_at_time(95, Duration::from_millis(1500)),
+        RGBColor::black()
+    );
+}
+
+#[test]
+fn channels() {
+    let (first, second) = (Recorder::default(), Recorder::default());
+    let mut monitor = Monitor::new(gray, first.clone());
+    let gradient = Gradient::new(&[(0., gray(0)), (100., gray(200))]).unwrap();
+    monitor.add_channel(gradient, second.clone());
+    monitor.set_load(50);
+    assert_eq!(first.colors(), vec![gray(50)]);
+    assert_eq!(second.colors(), vec![gray(100)]);
+    assert_eq!(monitor.colors(), vec![Some(gray(50)), Some(gray(100))]);
+    monitor.stop();
+    assert_eq!(second.colors().last(), Some(&RGBColor::black()));
+}
+
+#[test]
+fn run_openrgb() {
+    let (server, client) = setup();
+    let handle = ClientHandle::new(client);
+    let mut output = OpenRGBOutput::new(handle, Selector::DeviceType(DeviceType::Motherboard));
+    output.set_direct_mode(true);
+    // the mode is switched before the first color
+    assert!(!server
+        .requests()
+        .iter()
+        .any(|(_, r)| matches!(r, Request::SetCustomMode)));
+    let monitor = Mutex::new(Monitor::new(gray, output));
+    let e = monitor::run(
+        &monitor,
+        Values(vec![10, 10, 60]),
+        Duration::from_millis(10),
+    );
+    assert!(matches!(e, Error::Io(_)));
+    let mut monitor = monitor.into_inner().unwrap();
+    assert_eq!(monitor.load(), Some(60));
+    wait_for(|| server.controllers()[0].colors == vec![gray(60); 4]);
+    assert_eq!(server.controllers()[0].active_mode, 1);
+    monitor.close();
+    wait_for(|| server.controllers()[0].active_mode == 0);
+    assert_eq!(server.controllers()[3].colors, vec![RGBColor::black(); 10]);
+}
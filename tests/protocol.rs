@@ -0,0 +1,348 @@
+use rgbmon::capture::unpack_capture;
+use rgbmon::{
+    encode_packet, ControllerData, ControllerMetaData, DeviceType, Error, LedData, MatrixMap,
+    ModeData, PacketHeader, RGBColor, Request, Response, SegmentData, ZoneData, MAX_PACKET_SIZE,
+    MODE_COLORS_MODE_SPECIFIC, MODE_COLORS_PER_LED, MODE_FLAG_HAS_BRIGHTNESS,
+    MODE_FLAG_HAS_PER_LED_COLOR, PROTOCOL_VERSION, ZONE_TYPE_LINEAR, ZONE_TYPE_MATRIX,
+};
+
+fn mode(name: &str, protocol: u32) -> ModeData {
+    ModeData {
+        name: name.to_owned(),
+        value: 2,
+        flags: MODE_FLAG_HAS_PER_LED_COLOR | MODE_FLAG_HAS_BRIGHTNESS,
+        speed_min: 0,
+        speed_max: 4,
+        brightness_min: 0,
+        brightness_max: if protocol >= 3 { 100 } else { 0 },
+        colors_min: 1,
+        colors_max: 2,
+        speed: 1,
+        brightness: if protocol >= 3 { 50 } else { 0 },
+        direction: 0,
+        color_mode: MODE_COLORS_MODE_SPECIFIC,
+        colors: vec![RGBColor::new(1, 2, 3), RGBColor::new(250, 251, 252)],
+    }
+}
+
+/// Builds a controller with all fields, which are supported by the protocol version
+fn controller(protocol: u32) -> ControllerData {
+    let mut direct = mode("Direct", protocol);
+    direct.color_mode = MODE_COLORS_PER_LED;
+    direct.colors.clear();
+    ControllerData {
+        id: 3,
+        name: "Test Keyboard".to_owned(),
+        metadata: ControllerMetaData {
+            vendor: if protocol >= 1 {
+                "Vendor".to_owned()
+            } else {
+                String::new()
+            },
+            description: "Keyboard with a fan".to_owned(),
+            version: "1.0".to_owned(),
+            serial: "SN-1".to_owned(),
+            location: "HID: /dev/hidraw1".to_owned(),
+        },
+        device_type: DeviceType::Keyboard,
+        modes: vec![direct, mode("Breathing", protocol)],
+        active_mode: 1,
+        zones: vec![
+            ZoneData {
+                name: "Keys".to_owned(),
+                zone_type: ZONE_TYPE_MATRIX,
+                leds_min: 3,
+                leds_max: 3,
+                leds_count: 3,
+                matrix_map: Some(MatrixMap {
+                    height: 2,
+                    width: 2,
+                    map: vec![0, 1, 2, 0xFFFF_FFFF],
+                }),
+                segments: Vec::new(),
+            },
+            ZoneData {
+                name: "Fan".to_owned(),
+                zone_type: ZONE_TYPE_LINEAR,
+                leds_min: 0,
+                leds_max: 8,
+                leds_count: 2,
+                matrix_map: None,
+                segments: if protocol >= 4 {
+                    vec![SegmentData {
+                        name: "Fan top".to_owned(),
+                        segment_type: ZONE_TYPE_LINEAR,
+                        start_idx: 0,
+                        leds_count: 1,
+                    }]
+                } else {
+                    Vec::new()
+                },
+            },
+        ],
+        leds: [
+            "Key: Escape",
+            "Key: F1",
+            "Key: F2",
+            "Fan LED 1",
+            "Fan LED 2",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| LedData {
+            name: (*name).to_owned(),
+            value: i as u32,
+        })
+        .collect(),
+        colors: (0..5).map(|i| RGBColor::new(i * 10, 0, 255)).collect(),
+    }
+}
+
+#[test]
+fn controller_data_round_trip() {
+    for protocol in 0..=PROTOCOL_VERSION {
+        let c = controller(protocol);
+        let data = c.pack(protocol).unwrap();
+        assert_eq!(
+            u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize,
+            data.len()
+        );
+        let unpacked = ControllerData::unpack(c.id, protocol, &data).unwrap();
+        assert_eq!(unpacked, c, "protocol {}", protocol);
+        assert_eq!(unpacked.pack(protocol).unwrap(), data);
+    }
+}
+
+#[test]
+fn controller_data_byte_exact() {
+    let corpus: [&[u8]; 5] = [
+        include_bytes!("../fuzz/corpus/unpack/controller-v0"),
+        include_bytes!("../fuzz/corpus/unpack/controller-v1"),
+        include_bytes!("../fuzz/corpus/unpack/controller-v2"),
+        include_bytes!("../fuzz/corpus/unpack/controller-v3"),
+        include_bytes!("../fuzz/corpus/unpack/controller-v4"),
+    ];
+    for sample in &corpus {
+        let (protocol, data) = sample.split_first().unwrap();
+        let c = ControllerData::unpack(0, *protocol as u32, data).unwrap();
+        assert_eq!(c.pack(*protocol as u32).unwrap(), data);
+    }
+}
+
+#[test]
+fn controller_data_truncated() {
+    let data = controller(PROTOCOL_VERSION).pack(PROTOCOL_VERSION).unwrap();
+    for len in 0..data.len() {
+        match ControllerData::unpack(0, PROTOCOL_VERSION, &data[..len]) {
+            Err(Error::Malformed { offset, .. }) => assert!(offset <= len),
+            v => panic!("truncated at {}: {:?}", len, v),
+        }
+    }
+}
+
+#[test]
+fn controller_data_matrix_overflow() {
+    // 0x80000000 x 0x80000000 matrix with an 8-byte map length: the size must not wrap
+    let sample = include_bytes!("../fuzz/corpus/unpack/controller-v0-matrix-overflow");
+    let (protocol, data) = sample.split_first().unwrap();
+    match ControllerData::unpack(0, *protocol as u32, data) {
+        Err(Error::Malformed { field, .. }) => assert_eq!(field, "zone matrix map"),
+        v => panic!("{:?}", v),
+    }
+    let mut c = controller(PROTOCOL_VERSION);
+    c.zones[0].matrix_map.as_mut().unwrap().height = 3;
+    let data = c.pack(PROTOCOL_VERSION).unwrap();
+    assert!(matches!(
+        ControllerData::unpack(0, PROTOCOL_VERSION, &data),
+        Err(Error::Malformed { .. })
+    ));
+}
+
+#[test]
+fn matrix_map_get() {
+    let keys = controller(PROTOCOL_VERSION).zones[0]
+        .matrix_map
+        .clone()
+        .unwrap();
+    assert_eq!(keys.get(0, 1), Some(1));
+    assert_eq!(keys.get(1, 0), Some(2));
+    assert_eq!(keys.get(1, 1), None);
+    assert_eq!(keys.get(2, 0), None);
+    let huge = MatrixMap {
+        height: u32::MAX,
+        width: u32::MAX,
+        map: vec![0; 4],
+    };
+    assert_eq!(huge.get(0, 3), Some(0));
+    assert_eq!(huge.get(u32::MAX - 1, u32::MAX - 1), None);
+}
+
+#[test]
+fn device_type_names() {
+    for s in &["ledstrip", "LedStrip", "LED_STRIP", "led-strip", "4"] {
+        assert_eq!(
+            s.parse::<DeviceType>().unwrap(),
+            DeviceType::LedStrip,
+            "{}",
+            s
+        );
+    }
+    assert_eq!("0".parse::<DeviceType>().unwrap(), DeviceType::Motherboard);
+    assert_eq!("99".parse::<DeviceType>().unwrap(), DeviceType::Unknown(99));
+    let unknown: DeviceType = "Unknown".parse().unwrap();
+    assert_eq!(unknown, DeviceType::Unknown(21));
+    assert_eq!(unknown.to_string(), "unknown");
+    match "toaster".parse::<DeviceType>() {
+        Err(Error::InvalidValue(msg)) => assert_eq!(msg, "unknown device type: toaster"),
+        v => panic!("{:?}", v),
+    }
+    assert!("".parse::<DeviceType>().is_err());
+    for v in 0..=22 {
+        let t = DeviceType::from(v);
+        assert_eq!(t.to_string().parse::<DeviceType>().unwrap(), t, "{}", t);
+    }
+    assert_eq!(DeviceType::HeadsetStand.to_string(), "headsetstand");
+    assert_eq!(DeviceType::Unknown(99).to_string(), "99");
+}
+
+#[test]
+fn request_round_trip() {
+    let requests = vec![
+        Request::RequestControllerCount,
+        Request::RequestControllerData { protocol: 0 },
+        Request::RequestControllerData { protocol: 4 },
+        Request::RequestProtocolVersion(PROTOCOL_VERSION),
+        Request::SetClientName("rgbmon 0.0.2".to_owned()),
+        Request::RequestProfileList,
+        Request::SaveProfile("day".to_owned()),
+        Request::LoadProfile("night".to_owned()),
+        Request::DeleteProfile("old".to_owned()),
+        Request::UpdateLeds(vec![RGBColor::new(1, 2, 3); 4]),
+        Request::UpdateZoneLeds {
+            zone_id: 1,
+            colors: vec![RGBColor::new(4, 5, 6); 2],
+        },
+        Request::UpdateSingleLed {
+            led_id: 7,
+            color: RGBColor::new(7, 8, 9),
+        },
+        Request::SetCustomMode,
+        Request::UpdateMode {
+            mode_id: 1,
+            mode: mode("Breathing", PROTOCOL_VERSION),
+        },
+        Request::SaveMode {
+            mode_id: 1,
+            mode: mode("Breathing", PROTOCOL_VERSION),
+        },
+    ];
+    for request in requests {
+        let packet = request.encode(5, PROTOCOL_VERSION).unwrap();
+        let header = PacketHeader::unpack(&packet).unwrap();
+        assert_eq!(header.device_id, 5);
+        assert_eq!(header.packet_type, request.packet_type());
+        assert_eq!(header.len as usize, packet.len() - PacketHeader::SIZE);
+        let data = &packet[PacketHeader::SIZE..];
+        let unpacked = Request::unpack(header.packet_type, PROTOCOL_VERSION, data).unwrap();
+        assert_eq!(unpacked, request);
+        assert_eq!(unpacked.pack(PROTOCOL_VERSION).unwrap(), data);
+    }
+}
+
+#[test]
+fn response_round_trip() {
+    for protocol in 0..=PROTOCOL_VERSION {
+        let responses = vec![
+            Response::ControllerCount(2),
+            Response::ControllerData(Box::new(controller(protocol))),
+            Response::ProtocolVersion(protocol),
+            Response::ProfileList(vec!["day".to_owned(), "night".to_owned()]),
+            Response::ProfileList(Vec::new()),
+            Response::DeviceListUpdated,
+        ];
+        for response in responses {
+            let packet = response.encode(3, protocol).unwrap();
+            let header = PacketHeader::unpack(&packet).unwrap();
+            let data = &packet[PacketHeader::SIZE..];
+            let unpacked = Response::unpack(3, header.packet_type, protocol, data).unwrap();
+            assert_eq!(unpacked, response);
+            assert_eq!(unpacked.pack(protocol).unwrap(), data);
+        }
+    }
+}
+
+#[test]
+fn update_leds_wire_format() {
+    let packet = Request::UpdateLeds(vec![RGBColor::new(0x11, 0x22, 0x33)])
+        .encode(2, 4)
+        .unwrap();
+    assert_eq!(
+        packet,
+        vec![
+            b'O', b'R', b'G', b'B', 2, 0, 0, 0, 0x1A, 0x04, 0, 0, 10, 0, 0, 0, // header
+            10, 0, 0, 0, 1, 0, 0x11, 0x22, 0x33, 0
+        ]
+    );
+    assert_eq!(encode_packet(2, 1050, &packet[16..]), packet);
+}
+
+#[test]
+fn invalid_header() {
+    let mut packet = Response::ControllerCount(1).encode(0, 4).unwrap();
+    assert!(matches!(
+        PacketHeader::unpack(&packet[..10]),
+        Err(Error::Malformed { .. })
+    ));
+    packet[0] = b'X';
+    assert!(matches!(
+        PacketHeader::unpack(&packet),
+        Err(Error::Protocol(_))
+    ));
+}
+
+#[test]
+fn oversized_packet() {
+    let mut packet = Response::ControllerCount(1).encode(0, 4).unwrap();
+    packet[12..16].copy_from_slice(&(MAX_PACKET_SIZE + 1).to_le_bytes());
+    match PacketHeader::unpack(&packet) {
+        Err(Error::Malformed { offset, field, .. }) => {
+            assert_eq!((offset, field.as_str()), (12, "packet length"));
+        }
+        r => panic!("unexpected result: {:?}", r),
+    }
+    // the length is checked before reading the data
+    packet[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+    let mut capture = vec![b'<'];
+    capture.extend_from_slice(&packet[..PacketHeader::SIZE]);
+    assert!(matches!(
+        unpack_capture(&capture),
+        Err(Error::Malformed { .. })
+    ));
+}
+
+#[test]
+fn oversized_fields() {
+    let mut c = controller(PROTOCOL_VERSION);
+    c.name = "x".repeat(u16::MAX as usize);
+    assert!(matches!(
+        c.pack(PROTOCOL_VERSION),
+        Err(Error::InvalidValue(_))
+    ));
+    // the largest string, which fits, with the terminating zero
+    c.name.pop();
+    assert!(c.pack(PROTOCOL_VERSION).is_ok());
+    let mut c = controller(PROTOCOL_VERSION);
+    c.zones[1].matrix_map = Some(MatrixMap {
+        height: 128,
+        width: 128,
+        map: vec![0; 128 * 128],
+    });
+    assert!(matches!(
+        c.pack(PROTOCOL_VERSION),
+        Err(Error::InvalidValue(_))
+    ));
+    assert!(matches!(
+        Request::UpdateLeds(vec![RGBColor::black(); 70000]).encode(0, PROTOCOL_VERSION),
+        Err(Error::InvalidValue(_))
+    ));
+}
@@ -0,0 +1,302 @@
+mod common;
+
+use common::wait_for;
+use rgbmon::mock::{controller, MockServer};
+use rgbmon::selector::{select_leds, LedSelector, Selector};
+use rgbmon::{ControllerData, DeviceType, Error, LedData, OpenRGBClient, RGBColor, Request};
+
+fn controllers() -> Vec<ControllerData> {
+    let mut controllers = vec![
+        controller("ASUS ROG STRIX Z490", DeviceType::Motherboard, 4),
+        controller("Corsair Vengeance", DeviceType::Dram, 8),
+        controller("Corsair Vengeance", DeviceType::Dram, 8),
+        controller("Strip", DeviceType::LedStrip, 30),
+    ];
+    for (id, c) in controllers.iter_mut().enumerate() {
+        c.id = id as u32;
+        c.metadata.serial = format!("SN{}", id);
+        c.metadata.location = format!("I2C: /dev/i2c-{}", id);
+    }
+    controllers[0].metadata.vendor = "ASUS".to_owned();
+    controllers[1].metadata.vendor = "Corsair".to_owned();
+    controllers[2].metadata.vendor = "Corsair".to_owned();
+    controllers
+}
+
+fn selected(selector: &Selector) -> Vec<u32> {
+    selector
+        .select(&controllers())
+        .iter()
+        .map(|c| c.id)
+        .collect()
+}
+
+fn parsed(s: &str) -> Vec<u32> {
+    selected(&s.parse().unwrap())
+}
+
+#[test]
+fn matches() {
+    assert_eq!(selected(&Selector::Any), vec![0, 1, 2, 3]);
+    assert_eq!(selected(&Selector::Serial("SN2".to_owned())), vec![2]);
+    assert_eq!(
+        selected(&Selector::Location("I2C: /dev/i2c-1".to_owned())),
+        vec![1]
+    );
+    assert_eq!(
+        selected(&Selector::Vendor("Corsair".to_owned())),
+        vec![1, 2]
+    );
+    assert_eq!(
+        selected(&Selector::name_regex("(?i)strix").unwrap()),
+        vec![0]
+    );
+    assert_eq!(
+        selected(&Selector::name_glob("Corsair *").unwrap()),
+        vec![1, 2]
+    );
+    assert_eq!(selected(&Selector::name_glob("Stri?").unwrap()), vec![3]);
+    assert!(selected(&Selector::name_glob("Strip.").unwrap()).is_empty());
+    assert_eq!(
+        selected(&Selector::Vendor("Corsair".to_owned()).and(Selector::Serial("SN1".to_owned()))),
+        vec![1]
+    );
+    assert_eq!(
+        selected(&Selector::Id(3).or(Selector::DeviceType(DeviceType::Motherboard))),
+        vec![0, 3]
+    );
+    assert_eq!(
+        selected(&Selector::device_types(&[
+            DeviceType::Dram,
+            DeviceType::LedStrip
+        ])),
+        vec![1, 2, 3]
+    );
+}
+
+#[test]
+fn parse() {
+    assert_eq!(parsed("*"), vec![0, 1, 2, 3]);
+    assert_eq!(parsed("vendor=Corsair&serial=SN2"), vec![2]);
+    assert_eq!(parsed("name=*STRIX*;type=ledstrip"), vec![0, 3]);
+    assert_eq!(parsed("name~=^Corsair;id=0"), vec![0, 1, 2]);
+    assert_eq!(parsed("location=I2C: /dev/i2c-3"), vec![3]);
+    for s in &["", "vendor", "color=red", "id=x", "type=toaster", "name~=("] {
+        assert!(
+            matches!(s.parse::<Selector>(), Err(Error::InvalidValue(_))),
+            "{}",
+            s
+        );
+    }
+    let s: Selector = "vendor=ASUS&type=motherboard;serial=SN3".parse().unwrap();
+    assert_eq!(s.to_string(), "vendor=ASUS&type=motherboard;serial=SN3");
+}
+
+#[test]
+fn nested_round_trip() {
+    // the same way the daemon builds selectors for controllers without own gradients
+    let managed: Selector = "type=dram;type=motherboard".parse().unwrap();
+    let own: Selector = "vendor=Corsair&serial=SN1;name=*STRIX*".parse().unwrap();
+    let rest = managed.clone().and(!own);
+    assert_eq!(
+        rest.to_string(),
+        "type=dram&!vendor=Corsair&!name~=^.*STRIX.*$;\
+         type=dram&!serial=SN1&!name~=^.*STRIX.*$;\
+         type=motherboard&!vendor=Corsair&!name~=^.*STRIX.*$;\
+         type=motherboard&!serial=SN1&!name~=^.*STRIX.*$"
+    );
+    assert_eq!(selected(&rest), vec![2]);
+    let parsed: Selector = rest.to_string().parse().unwrap();
+    assert_eq!(selected(&parsed), vec![2]);
+    assert_eq!(parsed.to_string(), rest.to_string());
+    let none = managed.and(Selector::Or(Vec::new()));
+    assert_eq!(none.to_string(), "!*");
+    assert!(selected(&none.to_string().parse().unwrap()).is_empty());
+}
+
+#[test]
+fn set_color_by_selector() {
+    let server = MockServer::start(controllers()).unwrap();
+    let mut client = OpenRGBClient::new();
+    client.set_path(server.addr());
+    client.load().unwrap();
+    let color = RGBColor::new(1, 2, 3);
+    client
+        .set_color_by_selector(&"vendor=Corsair".parse().unwrap(), &color)
+        .unwrap();
+    wait_for(|| server.led_updates().len() == 2);
+    assert_eq!(
+        server.led_updates(),
+        vec![(1, vec![color; 8]), (2, vec![color; 8])]
+    );
+    match client.set_color_by_selector(&"serial=SN9".parse().unwrap(), &color) {
+        Err(Error::ControllerNotFound(s)) => assert_eq!(s, "serial=SN9"),
+        v => panic!("{:?}", v),
+    }
+}
+
+fn keyboard() -> ControllerData {
+    let mut keyboard = controller("Keyboard", DeviceType::Keyboard, 15);
+    let names = [
+        "Escape", "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12",
+    ]
+    .iter()
+    .map(|n| format!("Key: {}", n))
+    .chain(vec!["Key: Fn".to_owned(), "Key: Space".to_owned()]);
+    for (led, name) in keyboard.leds.iter_mut().zip(names) {
+        led.name = name;
+    }
+    keyboard
+}
+
+fn fans() -> ControllerData {
+    let mut fans = controller("Fans", DeviceType::Cooler, 8);
+    for (i, led) in fans.leds.iter_mut().enumerate() {
+        led.name = format!("Fan {} LED {}", i / 4 + 1, i % 4 + 1);
+    }
+    fans.id = 1;
+    fans
+}
+
+#[test]
+fn led_selectors() {
+    let controllers = vec![keyboard(), fans()];
+    let fkeys: LedSelector = "type=keyboard&led=Key: F[1-9]".parse().unwrap();
+    assert_eq!(
+        fkeys.select(&controllers),
+        vec![(0, (1..10).collect::<Vec<u32>>())]
+    );
+    let fkeys = vec![
+        fkeys,
+        LedSelector::new(Selector::DeviceType(DeviceType::Keyboard), "Key: F1[0-2]").unwrap(),
+    ];
+    assert_eq!(
+        select_leds(&fkeys, &controllers),
+        vec![(0, (1..13).collect::<Vec<u32>>())]
+    );
+    let re: LedSelector = r"led~=^Key: F([1-9]|1[0-2])$".parse().unwrap();
+    assert_eq!(re.select(&controllers), select_leds(&fkeys, &controllers));
+    let fan: LedSelector = "name=Fans&led=Fan 2 *".parse().unwrap();
+    assert_eq!(fan.select(&controllers), vec![(1, vec![4, 5, 6, 7])]);
+    let not_esc = LedSelector::new(Selector::Any, "Key: [!E]*").unwrap();
+    assert_eq!(not_esc.select(&controllers)[0].1.len(), 14);
+    assert_eq!(fan.to_string(), "name~=^Fans$&led~=^Fan 2 .*$");
+    // alternatives share the LED name condition
+    let leds_1: LedSelector = "name=Fans;type=keyboard&led=* 1".parse().unwrap();
+    assert_eq!(leds_1.select(&controllers), vec![(1, vec![0, 4])]);
+    for s in &[
+        "type=keyboard",
+        "led=a&led=b",
+        "color=red&led=*",
+        "led~=(",
+        "type=keyboard&led=a;type=cooler&led=b",
+    ] {
+        assert!(
+            matches!(s.parse::<LedSelector>(), Err(Error::InvalidValue(_))),
+            "{}",
+            s
+        );
+    }
+}
+
+#[test]
+fn led_selector_round_trip() {
+    let controllers = vec![keyboard(), fans()];
+    // the same way the daemon limits --leds rules to the device types
+    let keys = LedSelector::new(
+        Selector::Vendor("Mock".to_owned()).and(Selector::device_types(&[
+            DeviceType::Keyboard,
+            DeviceType::Cooler,
+        ])),
+        "*1",
+    )
+    .unwrap();
+    assert_eq!(
+        keys.to_string(),
+        "vendor=Mock&type=keyboard&led~=^.*1$;vendor=Mock&type=cooler&led~=^.*1$"
+    );
+    let parsed: LedSelector = keys.to_string().parse().unwrap();
+    assert_eq!(parsed.to_string(), keys.to_string());
+    assert_eq!(parsed.select(&controllers), keys.select(&controllers));
+    assert_eq!(
+        keys.select(&controllers),
+        vec![(0, vec![1, 11]), (1, vec![0, 4])]
+    );
+    let none = LedSelector::new(Selector::Or(Vec::new()), "*").unwrap();
+    let parsed: LedSelector = none.to_string().parse().unwrap();
+    assert!(parsed.select(&controllers).is_empty());
+}
+
+#[test]
+fn set_leds_color() {
+    let server = MockServer::start(vec![keyboard(), fans()]).unwrap();
+    let mut client = OpenRGBClient::new();
+    client.set_path(server.addr());
+    client.load().unwrap();
+    let color = RGBColor::new(1, 2, 3);
+    let selectors = vec![
+        "led=Key: F?".parse().unwrap(),
+        "type=cooler&led=*".parse().unwrap(),
+    ];
+    client.set_leds_color(&selectors, &color).unwrap();
+    wait_for(|| server.led_updates().len() == 2);
+    // a single frame for each controller, the rest of LEDs keep their colors
+    assert_eq!(server.led_updates()[1], (1, vec![color; 8]));
+    let is_f_key = |name: &str| name.len() == 7 && name.starts_with("Key: F");
+    let keyboard = &server.controllers()[0];
+    for (led, c) in keyboard.leds.iter().zip(&keyboard.colors) {
+        assert_eq!(*c == color, is_f_key(&led.name), "{}", led.name);
+    }
+    // LEDs, changed by other clients, keep their colors
+    let mut other_client = OpenRGBClient::new();
+    other_client.set_path(server.addr());
+    other_client.load().unwrap();
+    let changed = RGBColor::new(7, 8, 9);
+    other_client.set_led_color(0, 0, &changed).unwrap();
+    wait_for(|| server.controllers()[0].colors[0] == changed);
+    let other = RGBColor::new(4, 5, 6);
+    client
+        .set_leds_color(&["led=Key: Escape".parse().unwrap()], &other)
+        .unwrap();
+    wait_for(|| server.led_updates().len() == 3);
+    let keyboard = &server.controllers()[0];
+    for (i, (led, c)) in keyboard.leds.iter().zip(&keyboard.colors).enumerate() {
+        match led.name.as_str() {
+            "Key: Escape" => assert_eq!(*c, other),
+            _ if i == 0 => assert_eq!(*c, changed),
+            name => assert_eq!(*c == color, is_f_key(name), "{}", name),
+        }
+    }
+    assert_eq!(
+        server
+            .requests()
+            .iter()
+            .filter(|(_, r)| matches!(r, Request::UpdateSingleLed { .. }))
+            .count(),
+        1
+    );
+    // LEDs are set one by one if the server has other LEDs, until reloaded
+    let mut extended = keyboard.clone();
+    extended.leds.push(LedData {
+        name: "Key: Media".to_owned(),
+        value: 0,
+    });
+    extended.colors.push(RGBColor::black());
+    server.set_controllers(vec![extended, fans()]);
+    client
+        .set_leds_color(&["led=Key: F1?".parse().unwrap()], &other)
+        .unwrap();
+    wait_for(|| {
+        server.controllers()[0]
+            .colors
+            .iter()
+            .filter(|c| **c == other)
+            .count()
+            == 4
+    });
+    assert_eq!(server.led_updates().len(), 3);
+    match client.set_leds_color(&["led=Key: Enter".parse().unwrap()], &color) {
+        Err(Error::NotFound(s)) => assert_eq!(s, "LEDs led~=^Key: Enter$"),
+        v => panic!("{:?}", v),
+    }
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::{mock_server, wait_for};
+use rgbmon::mock::controller;
+use rgbmon::transport::{duplex, Connector, TcpConnector, Transport};
+use rgbmon::{DeviceType, Notification, OpenRGBClient, RGBColor};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn duplex_stream() {
+    let (mut a, mut b) = duplex();
+    a.write_all(b"hello").unwrap();
+    let mut buf = [0u8; 5];
+    b.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    b.set_nonblocking(true).unwrap();
+    assert_eq!(
+        b.read(&mut buf).unwrap_err().kind(),
+        std::io::ErrorKind::WouldBlock
+    );
+    b.set_nonblocking(false).unwrap();
+    b.set_timeout(Duration::from_millis(10)).unwrap();
+    assert_eq!(
+        b.read(&mut buf).unwrap_err().kind(),
+        std::io::ErrorKind::TimedOut
+    );
+    // the connection stays open while a clone exists
+    let a2 = a.try_clone().unwrap();
+    drop(a);
+    a2.shutdown().unwrap();
+    assert_eq!(b.read(&mut buf).unwrap(), 0);
+    assert!(b.write_all(b"x").is_err());
+}
+
+#[test]
+fn memory() {
+    let server = mock_server();
+    let mut client = OpenRGBClient::new();
+    client.set_connector(server.connector());
+    client.load().unwrap();
+    assert_eq!(client.controllers, server.controllers());
+    let color = RGBColor::new(1, 2, 3);
+    client.set_color(&color).unwrap();
+    wait_for(|| server.controllers().iter().all(|c| c.colors[0] == color));
+    server.set_controllers(vec![controller("Strip", DeviceType::LedStrip, 30)]);
+    wait_for(|| {
+        client
+            .poll_notifications()
+            .unwrap()
+            .contains(&Notification::DeviceListUpdated)
+    });
+    client.reload().unwrap();
+    assert_eq!(client.controllers[0].name, "Strip");
+}
+
+#[test]
+fn reconnect() {
+    let server = mock_server();
+    let mut client = OpenRGBClient::new();
+    client.set_connector(server.connector());
+    client.load().unwrap();
+    server.disconnect();
+    let color = RGBColor::new(4, 5, 6);
+    client.set_color_by_id(1, &color).unwrap();
+    wait_for(|| server.controllers()[1].colors[0] == color);
+    server.disconnect();
+    assert!(client.profiles().unwrap().is_empty());
+}
+
+#[test]
+fn tcp_host_name() {
+    let server = mock_server();
+    let port = server.addr().rsplit(':').next().unwrap();
+    let mut client = OpenRGBClient::new();
+    client.set_path(&format!("localhost:{}", port));
+    client.load().unwrap();
+    assert_eq!(client.controllers, server.controllers());
+}
+
+#[test]
+fn tcp_ipv6() {
+    // IPv6 may be disabled in the test environment
+    let listener = match TcpListener::bind("[::1]:0") {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+    let addr = listener.local_addr().unwrap();
+    let connector = TcpConnector::new(&format!("[::1]:{}", addr.port()));
+    let mut stream = connector.connect(Duration::from_secs(1)).unwrap();
+    stream.write_all(b"ORGB").unwrap();
+    let mut buf = [0u8; 4];
+    listener.accept().unwrap().0.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"ORGB");
+}
+
+#[cfg(unix)]
+#[test]
+fn unix_socket() {
+    use std::os::unix::net::UnixListener;
+    let server = mock_server();
+    let path = std::env::temp_dir().join(format!("rgbmon-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let addr = server.addr().to_owned();
+    // forwards the socket to the server, the way socat or ssh do
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut local = stream.unwrap();
+            let mut remote = std::net::TcpStream::connect(&addr).unwrap();
+            let mut local_rx = local.try_clone().unwrap();
+            let mut remote_tx = remote.try_clone().unwrap();
+            thread::spawn(move || std::io::copy(&mut local_rx, &mut remote_tx));
+            thread::spawn(move || std::io::copy(&mut remote, &mut local));
+        }
+    });
+    let mut client = OpenRGBClient::new();
+    client.set_path(&format!("unix:{}", path.display()));
+    client.load().unwrap();
+    assert_eq!(client.controllers, server.controllers());
+    let color = RGBColor::new(7, 8, 9);
+    client.set_color_by_id(0, &color).unwrap();
+    wait_for(|| server.controllers()[0].colors[0] == color);
+    let _ = std::fs::remove_file(&path);
+}